@@ -0,0 +1,231 @@
+//! State of the alarm rules configured for a datapoint.
+//!
+//! Limit alarms trigger when the scaled value moves past the limit and clear
+//! once it is back inside by at least `hysteresis`. Equality and bit alarms
+//! follow the value directly.
+
+use crate::config::{AlarmCondition, AlarmRule, EnumLabel, Severity};
+use crate::datapoint::Datapoint;
+use chrono::{DateTime, Local};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone)]
+pub struct Alarm {
+    pub rule: AlarmRule,
+    /// Code compared by `equals`/`not_equals`
+    code: Option<i64>,
+    /// Time the alarm became active, `None` while it is inactive
+    pub since: Option<DateTime<Local>>,
+}
+
+impl Alarm {
+    pub fn new(rule: AlarmRule, enum_labels: &BTreeMap<i64, EnumLabel>) -> Self {
+        let code = match rule.condition {
+            AlarmCondition::Equals { ref value } | AlarmCondition::NotEquals { ref value } => {
+                value.resolve(enum_labels)
+            }
+            _ => None,
+        };
+        Self {
+            rule,
+            code,
+            since: None,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.since.is_some()
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.rule.severity()
+    }
+
+    /// Short name for the status column.
+    pub fn label(&self) -> &'static str {
+        match self.rule.condition {
+            AlarmCondition::High { .. } => "HIGH",
+            AlarmCondition::HighHigh { .. } => "HIGH HIGH",
+            AlarmCondition::Low { .. } => "LOW",
+            AlarmCondition::LowLow { .. } => "LOW LOW",
+            _ => "ALARM",
+        }
+    }
+
+    /// Condition in words, e.g. "high > 80" or "bit 3 set".
+    pub fn describe(&self) -> String {
+        match self.rule.condition {
+            AlarmCondition::High { limit } => format!("high > {}", limit),
+            AlarmCondition::HighHigh { limit } => format!("high high > {}", limit),
+            AlarmCondition::Low { limit } => format!("low < {}", limit),
+            AlarmCondition::LowLow { limit } => format!("low low < {}", limit),
+            AlarmCondition::Equals { ref value } => format!("= {}", value),
+            AlarmCondition::NotEquals { ref value } => format!("!= {}", value),
+            AlarmCondition::BitSet { bit } => format!("bit {} set", bit),
+            AlarmCondition::BitCleared { bit } => format!("bit {} cleared", bit),
+        }
+    }
+
+    /// Re-evaluates the rule against the current value of `dp`. Leaves the
+    /// state unchanged when the value can't be checked. Returns the new
+    /// state if it changed.
+    pub fn update(&mut self, dp: &Datapoint, now: DateTime<Local>) -> Option<bool> {
+        let active = self.is_active();
+        let hysteresis = if active { self.rule.hysteresis } else { 0.0 };
+        let value = dp.value.as_ref();
+
+        let triggered = match self.rule.condition {
+            AlarmCondition::High { limit } | AlarmCondition::HighHigh { limit } => {
+                dp.engineering_value().map(|v| v > limit - hysteresis)
+            }
+            AlarmCondition::Low { limit } | AlarmCondition::LowLow { limit } => {
+                dp.engineering_value().map(|v| v < limit + hysteresis)
+            }
+            AlarmCondition::Equals { .. } => value
+                .and_then(|v| v.as_i64())
+                .zip(self.code)
+                .map(|(v, code)| v == code),
+            AlarmCondition::NotEquals { .. } => value
+                .and_then(|v| v.as_i64())
+                .zip(self.code)
+                .map(|(v, code)| v != code),
+            AlarmCondition::BitSet { bit } => dp.bit(bit),
+            AlarmCondition::BitCleared { bit } => dp.bit(bit).map(|set| !set),
+        };
+
+        match triggered {
+            Some(true) if !active => {
+                self.since = Some(now);
+                Some(true)
+            }
+            Some(false) if active => {
+                self.since = None;
+                Some(false)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::datapoint::DataValue;
+    use crate::scanner::new_datapoint;
+    use serde_json::{Value, json};
+
+    /// Datapoint with `rule` as its only alarm rule, and the alarm state.
+    fn with_rule(datapoint: Value, rule: Value) -> (Datapoint, Alarm) {
+        let mut datapoint = datapoint;
+        datapoint["name"] = json!("dp");
+        datapoint["address"] = json!(0);
+        datapoint["alarms"] = json!([rule]);
+        let config = Config::from_json(json!({
+            "server": { "protocol": "modbus", "host": "127.0.0.1" },
+            "datapoints": [datapoint],
+        }))
+        .unwrap();
+        let mut dp = new_datapoint(&config.servers[0], &config.datapoints[0], 1);
+        let alarm = dp.alarms.remove(0);
+        (dp, alarm)
+    }
+
+    /// Feeds the values in turn; returns the state change after each.
+    fn run(
+        dp: &mut Datapoint,
+        alarm: &mut Alarm,
+        values: &[Option<DataValue>],
+    ) -> Vec<Option<bool>> {
+        values
+            .iter()
+            .map(|value| {
+                dp.value = value.clone();
+                alarm.update(dp, Local::now())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn high_clears_below_limit_minus_hysteresis() {
+        let (mut dp, mut alarm) = with_rule(
+            json!({ "length": 1, "scale": 0.1 }),
+            json!({ "when": "high", "limit": 80, "hysteresis": 2 }),
+        );
+        let raw = |tenths: u16| Some(DataValue::U16(tenths));
+        let changes = run(
+            &mut dp,
+            &mut alarm,
+            &[raw(800), raw(801), raw(790), raw(781), raw(780), raw(790)],
+        );
+        assert_eq!(changes, [None, Some(true), None, None, Some(false), None]);
+        assert!(!alarm.is_active());
+    }
+
+    #[test]
+    fn low_clears_above_limit_plus_hysteresis() {
+        let (mut dp, mut alarm) = with_rule(
+            json!({ "length": 1, "data_type": "i16" }),
+            json!({ "when": "low_low", "limit": -10, "hysteresis": 1 }),
+        );
+        let value = |v: i16| Some(DataValue::I16(v));
+        let changes = run(
+            &mut dp,
+            &mut alarm,
+            &[value(-10), value(-11), value(-10), value(-9), value(-10)],
+        );
+        assert_eq!(changes, [None, Some(true), None, Some(false), None]);
+        assert_eq!(alarm.severity(), Severity::Critical);
+    }
+
+    #[test]
+    fn unreadable_value_keeps_the_state() {
+        let (mut dp, mut alarm) = with_rule(
+            json!({ "length": 1 }),
+            json!({ "when": "high", "limit": 5 }),
+        );
+        let changes = run(&mut dp, &mut alarm, &[Some(DataValue::U16(6)), None]);
+        assert_eq!(changes, [Some(true), None]);
+        assert!(alarm.is_active());
+    }
+
+    #[test]
+    fn equals_matches_enum_labels() {
+        let labels = json!({ "length": 1, "enum": { "0": "Off", "1": "Run", "3": "Fault" } });
+        let states = [0, 3, 3, 1].map(|v| Some(DataValue::U16(v)));
+
+        let (mut dp, mut alarm) = with_rule(
+            labels.clone(),
+            json!({ "when": "equals", "value": "fault" }),
+        );
+        assert_eq!(
+            run(&mut dp, &mut alarm, &states),
+            [None, Some(true), None, Some(false)]
+        );
+        assert_eq!(alarm.describe(), "= fault");
+
+        let (mut dp, mut alarm) = with_rule(labels, json!({ "when": "not_equals", "value": 1 }));
+        assert_eq!(
+            run(&mut dp, &mut alarm, &states),
+            [Some(true), None, None, Some(false)]
+        );
+    }
+
+    #[test]
+    fn bit_alarms_follow_the_bit() {
+        let bits = [0b1000, 0, 0b1000].map(|v| Some(DataValue::Bitfield(v)));
+        let bitfield = json!({ "length": 1, "data_type": "bitfield" });
+
+        let (mut dp, mut alarm) =
+            with_rule(bitfield.clone(), json!({ "when": "bit_set", "bit": 3 }));
+        assert_eq!(
+            run(&mut dp, &mut alarm, &bits),
+            [Some(true), Some(false), Some(true)]
+        );
+        let (mut dp, mut alarm) = with_rule(bitfield, json!({ "when": "bit_cleared", "bit": 3 }));
+        assert_eq!(
+            run(&mut dp, &mut alarm, &bits),
+            [None, Some(true), Some(false)]
+        );
+    }
+}
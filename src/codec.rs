@@ -0,0 +1,379 @@
+//! Conversion between register words and typed values.
+//!
+//! Multi-register values are described by the order in which the bytes of
+//! the big-endian value (A = most significant byte) appear on the wire:
+//!
+//! | Order  | 32-bit registers | Common name                 |
+//! |--------|------------------|-----------------------------|
+//! | `ABCD` | `AB CD`          | big endian                  |
+//! | `CDAB` | `CD AB`          | word swapped                |
+//! | `BADC` | `BA DC`          | byte swapped                |
+//! | `DCBA` | `DC BA`          | little endian               |
+//!
+//! Wider values follow the same pattern, e.g. CDAB reverses the order of all
+//! registers of a 64-bit value while keeping the bytes within each register.
+
+use crate::config::{ByteOrder, DataType};
+use crate::datapoint::DataValue;
+
+/// Reorders register words into the big-endian byte sequence of the value.
+fn words_to_bytes(words: &[u16], order: ByteOrder) -> Vec<u8> {
+    let reverse_words = matches!(order, ByteOrder::Cdab | ByteOrder::Dcba);
+    let swap_bytes = matches!(order, ByteOrder::Badc | ByteOrder::Dcba);
+
+    let ordered: Box<dyn Iterator<Item = &u16>> = if reverse_words {
+        Box::new(words.iter().rev())
+    } else {
+        Box::new(words.iter())
+    };
+    ordered
+        .flat_map(|&word| {
+            let word = if swap_bytes { word.swap_bytes() } else { word };
+            word.to_be_bytes()
+        })
+        .collect()
+}
+
+/// Inverse of [`words_to_bytes`].
+fn bytes_to_words(bytes: &[u8], order: ByteOrder) -> Vec<u16> {
+    let reverse_words = matches!(order, ByteOrder::Cdab | ByteOrder::Dcba);
+    let swap_bytes = matches!(order, ByteOrder::Badc | ByteOrder::Dcba);
+
+    let mut words: Vec<u16> = bytes
+        .chunks(2)
+        .map(|pair| {
+            let word = u16::from_be_bytes([pair[0], pair.get(1).copied().unwrap_or(0)]);
+            if swap_bytes { word.swap_bytes() } else { word }
+        })
+        .collect();
+    if reverse_words {
+        words.reverse();
+    }
+    words
+}
+
+/// Decodes the registers of a datapoint. Returns `None` when fewer registers
+/// than the data type needs were read.
+pub fn decode_value(data_type: DataType, registers: &[u16], order: ByteOrder) -> Option<DataValue> {
+    let all_registers = registers;
+    let registers = registers.get(..data_type.register_count() as usize)?;
+
+    // Bit numbers of 16-bit bitfields refer to the register as read
+    if data_type == DataType::Bitfield {
+        return Some(match *all_registers {
+            [high, low, ..] => {
+                let bytes = words_to_bytes(&[high, low], order);
+                DataValue::Bitfield32(u32::from_be_bytes(bytes.try_into().ok()?))
+            }
+            _ => DataValue::Bitfield(registers[0]),
+        });
+    }
+
+    if data_type == DataType::String {
+        return Some(DataValue::String(decode_string(&words_to_bytes(
+            all_registers,
+            order,
+        ))));
+    }
+
+    let bytes = words_to_bytes(registers, order);
+    let value = match data_type {
+        DataType::U16 => DataValue::U16(u16::from_be_bytes([bytes[0], bytes[1]])),
+        DataType::I16 => DataValue::I16(i16::from_be_bytes([bytes[0], bytes[1]])),
+        DataType::U32 => DataValue::U32(u32::from_be_bytes(bytes[..4].try_into().ok()?)),
+        DataType::I32 => DataValue::I32(i32::from_be_bytes(bytes[..4].try_into().ok()?)),
+        DataType::F32 => DataValue::F32(f32::from_be_bytes(bytes[..4].try_into().ok()?)),
+        DataType::U64 => DataValue::U64(u64::from_be_bytes(bytes[..8].try_into().ok()?)),
+        DataType::I64 => DataValue::I64(i64::from_be_bytes(bytes[..8].try_into().ok()?)),
+        DataType::F64 => DataValue::F64(f64::from_be_bytes(bytes[..8].try_into().ok()?)),
+        DataType::Bitfield | DataType::String => unreachable!(),
+    };
+    Some(value)
+}
+
+/// Encodes a value into the registers to write, in wire order.
+pub fn encode_value(value: &DataValue, order: ByteOrder) -> Vec<u16> {
+    match *value {
+        DataValue::U16(v) => bytes_to_words(&v.to_be_bytes(), order),
+        DataValue::I16(v) => bytes_to_words(&v.to_be_bytes(), order),
+        DataValue::U32(v) => bytes_to_words(&v.to_be_bytes(), order),
+        DataValue::I32(v) => bytes_to_words(&v.to_be_bytes(), order),
+        DataValue::F32(v) => bytes_to_words(&v.to_be_bytes(), order),
+        DataValue::U64(v) => bytes_to_words(&v.to_be_bytes(), order),
+        DataValue::I64(v) => bytes_to_words(&v.to_be_bytes(), order),
+        DataValue::F64(v) => bytes_to_words(&v.to_be_bytes(), order),
+        DataValue::Bitfield(v) => vec![v],
+        DataValue::Bitfield32(v) => bytes_to_words(&v.to_be_bytes(), order),
+        DataValue::String(ref text) => bytes_to_words(text.as_bytes(), order),
+        DataValue::Bool(v) => vec![v as u16],
+        DataValue::Bits(ref bits) => bits.iter().map(|&bit| bit as u16).collect(),
+    }
+}
+
+/// Register offset and bit within that register holding `bit` of a
+/// 32-bit bitfield stored with the given order.
+pub fn bitfield32_bit_location(bit: u8, order: ByteOrder) -> (u16, u8) {
+    let words = encode_value(&DataValue::Bitfield32(1 << (bit % 32)), order);
+    let offset = words.iter().position(|&w| w != 0).unwrap_or(0);
+    (offset as u16, words[offset].trailing_zeros() as u8)
+}
+
+/// Encodes text into `length` registers, padded with NUL bytes.
+pub fn encode_string(text: &str, length: u16, order: ByteOrder) -> Vec<u16> {
+    let mut bytes = text.as_bytes().to_vec();
+    bytes.resize((length as usize * 2).max(bytes.len()), 0);
+    bytes_to_words(&bytes, order)
+}
+
+/// Text up to the first NUL, without surrounding space padding. Invalid
+/// UTF-8 is replaced rather than rejected.
+fn decode_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).trim().to_string()
+}
+
+/// Decodes coils or discrete inputs, one word per bit.
+pub fn decode_bits(words: &[u16]) -> Option<DataValue> {
+    match words {
+        [] => None,
+        [word] => Some(DataValue::Bool(*word != 0)),
+        _ => Some(DataValue::Bits(words.iter().map(|&w| w != 0).collect())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORDERS: [ByteOrder; 4] = [
+        ByteOrder::Abcd,
+        ByteOrder::Cdab,
+        ByteOrder::Badc,
+        ByteOrder::Dcba,
+    ];
+
+    // 123.456f32 = 0x42F6E979
+    const F32_VECTORS: [(ByteOrder, [u16; 2]); 4] = [
+        (ByteOrder::Abcd, [0x42F6, 0xE979]),
+        (ByteOrder::Cdab, [0xE979, 0x42F6]),
+        (ByteOrder::Badc, [0xF642, 0x79E9]),
+        (ByteOrder::Dcba, [0x79E9, 0xF642]),
+    ];
+
+    #[test]
+    fn decodes_f32_in_all_orders() {
+        for (order, words) in F32_VECTORS {
+            match decode_value(DataType::F32, &words, order) {
+                Some(DataValue::F32(v)) => assert_eq!(v, 123.456, "{:?}", order),
+                other => panic!("{:?}: unexpected {:?}", order, other),
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_u32_and_i32_in_all_orders() {
+        let vectors = [
+            (ByteOrder::Abcd, [0x1234, 0x5678]),
+            (ByteOrder::Cdab, [0x5678, 0x1234]),
+            (ByteOrder::Badc, [0x3412, 0x7856]),
+            (ByteOrder::Dcba, [0x7856, 0x3412]),
+        ];
+        for (order, words) in vectors {
+            assert!(matches!(
+                decode_value(DataType::U32, &words, order),
+                Some(DataValue::U32(0x1234_5678))
+            ));
+        }
+
+        // -2 = 0xFFFFFFFE
+        assert!(matches!(
+            decode_value(DataType::I32, &[0xFFFE, 0xFFFF], ByteOrder::Cdab),
+            Some(DataValue::I32(-2))
+        ));
+        assert!(matches!(
+            decode_value(DataType::I32, &[0xFEFF, 0xFFFF], ByteOrder::Dcba),
+            Some(DataValue::I32(-2))
+        ));
+    }
+
+    #[test]
+    fn swaps_bytes_of_16_bit_values() {
+        assert!(matches!(
+            decode_value(DataType::U16, &[0x1234], ByteOrder::Cdab),
+            Some(DataValue::U16(0x1234))
+        ));
+        assert!(matches!(
+            decode_value(DataType::U16, &[0x1234], ByteOrder::Badc),
+            Some(DataValue::U16(0x3412))
+        ));
+        assert!(matches!(
+            decode_value(DataType::I16, &[0xFFFE], ByteOrder::Dcba),
+            Some(DataValue::I16(-257))
+        ));
+    }
+
+    #[test]
+    fn keeps_bitfields_as_read() {
+        for order in ORDERS {
+            assert!(matches!(
+                decode_value(DataType::Bitfield, &[0x0102], order),
+                Some(DataValue::Bitfield(0x0102))
+            ));
+        }
+    }
+
+    #[test]
+    fn decodes_32_bit_bitfields() {
+        assert!(matches!(
+            decode_value(DataType::Bitfield, &[0x8000, 0x0001], ByteOrder::Abcd),
+            Some(DataValue::Bitfield32(0x8000_0001))
+        ));
+        assert!(matches!(
+            decode_value(DataType::Bitfield, &[0x0001, 0x8000], ByteOrder::Cdab),
+            Some(DataValue::Bitfield32(0x8000_0001))
+        ));
+
+        assert_eq!(bitfield32_bit_location(0, ByteOrder::Abcd), (1, 0));
+        assert_eq!(bitfield32_bit_location(31, ByteOrder::Abcd), (0, 15));
+        assert_eq!(bitfield32_bit_location(0, ByteOrder::Cdab), (0, 0));
+        assert_eq!(bitfield32_bit_location(17, ByteOrder::Cdab), (1, 1));
+        assert_eq!(bitfield32_bit_location(0, ByteOrder::Badc), (1, 8));
+        assert_eq!(bitfield32_bit_location(8, ByteOrder::Dcba), (0, 0));
+    }
+
+    #[test]
+    fn reorders_wider_values() {
+        let words = [0x0102, 0x0304, 0x0506, 0x0708];
+        assert_eq!(
+            words_to_bytes(&words, ByteOrder::Abcd),
+            [1, 2, 3, 4, 5, 6, 7, 8]
+        );
+        assert_eq!(
+            words_to_bytes(&words, ByteOrder::Cdab),
+            [7, 8, 5, 6, 3, 4, 1, 2]
+        );
+        assert_eq!(
+            words_to_bytes(&words, ByteOrder::Badc),
+            [2, 1, 4, 3, 6, 5, 8, 7]
+        );
+        assert_eq!(
+            words_to_bytes(&words, ByteOrder::Dcba),
+            [8, 7, 6, 5, 4, 3, 2, 1]
+        );
+    }
+
+    #[test]
+    fn decodes_64_bit_values_in_all_orders() {
+        // 0x0102030405060708 and 1.5f64 = 0x3FF8000000000000
+        let vectors = [
+            (
+                ByteOrder::Abcd,
+                [0x0102, 0x0304, 0x0506, 0x0708],
+                [0x3FF8, 0, 0, 0],
+            ),
+            (
+                ByteOrder::Cdab,
+                [0x0708, 0x0506, 0x0304, 0x0102],
+                [0, 0, 0, 0x3FF8],
+            ),
+            (
+                ByteOrder::Badc,
+                [0x0201, 0x0403, 0x0605, 0x0807],
+                [0xF83F, 0, 0, 0],
+            ),
+            (
+                ByteOrder::Dcba,
+                [0x0807, 0x0605, 0x0403, 0x0201],
+                [0, 0, 0, 0xF83F],
+            ),
+        ];
+        for (order, int_words, float_words) in vectors {
+            assert!(
+                matches!(
+                    decode_value(DataType::U64, &int_words, order),
+                    Some(DataValue::U64(0x0102_0304_0506_0708))
+                ),
+                "{:?}",
+                order
+            );
+            assert!(
+                matches!(
+                    decode_value(DataType::F64, &float_words, order),
+                    Some(DataValue::F64(1.5))
+                ),
+                "{:?}",
+                order
+            );
+            assert_eq!(
+                encode_value(&DataValue::U64(0x0102_0304_0506_0708), order),
+                int_words
+            );
+        }
+
+        assert!(matches!(
+            decode_value(
+                DataType::I64,
+                &[0xFFFF, 0xFFFF, 0xFFFF, 0xFFFE],
+                ByteOrder::Abcd
+            ),
+            Some(DataValue::I64(-2))
+        ));
+    }
+
+    #[test]
+    fn decodes_strings() {
+        // "SN-1234" padded with NUL
+        let words = [0x534E, 0x2D31, 0x3233, 0x3400, 0x0000];
+        assert!(matches!(
+            decode_value(DataType::String, &words, ByteOrder::Abcd),
+            Some(DataValue::String(ref s)) if s == "SN-1234"
+        ));
+
+        let swapped = [0x4E53, 0x312D, 0x3332, 0x0034];
+        assert!(matches!(
+            decode_value(DataType::String, &swapped, ByteOrder::Badc),
+            Some(DataValue::String(ref s)) if s == "SN-1234"
+        ));
+
+        // Space padding and UTF-8
+        let words = [0x2020, 0xC3A9, 0x7420, 0x2020];
+        assert!(matches!(
+            decode_value(DataType::String, &words, ByteOrder::Abcd),
+            Some(DataValue::String(ref s)) if s == "ét"
+        ));
+
+        assert_eq!(
+            encode_string("abc", 3, ByteOrder::Abcd),
+            [0x6162, 0x6300, 0]
+        );
+        assert_eq!(
+            encode_string("abc", 3, ByteOrder::Badc),
+            [0x6261, 0x0063, 0]
+        );
+    }
+
+    #[test]
+    fn rejects_short_reads() {
+        assert!(decode_value(DataType::U32, &[0x1234], ByteOrder::Abcd).is_none());
+        assert!(decode_value(DataType::U16, &[], ByteOrder::Abcd).is_none());
+        assert!(decode_value(DataType::F64, &[0, 0, 0], ByteOrder::Abcd).is_none());
+    }
+
+    #[test]
+    fn encode_is_inverse_of_decode() {
+        for (order, words) in F32_VECTORS {
+            assert_eq!(encode_value(&DataValue::F32(123.456), order), words);
+        }
+        for order in ORDERS {
+            for value in [DataValue::I32(-123_456), DataValue::U16(0xBEEF)] {
+                let data_type = match value {
+                    DataValue::I32(_) => DataType::I32,
+                    _ => DataType::U16,
+                };
+                let words = encode_value(&value, order);
+                let decoded = decode_value(data_type, &words, order).unwrap();
+                assert_eq!(decoded.to_string(), value.to_string(), "{:?}", order);
+            }
+        }
+    }
+}
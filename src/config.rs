@@ -1,34 +1,170 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::{fs, path::Path};
+use std::{collections::BTreeMap, fmt, fs, path::Path, time::Duration};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Config {
-    pub server: ServerConfig,
+    /// Single-server form, kept for existing configs. Moved into `servers`
+    /// on load.
+    #[serde(default, skip_serializing)]
+    server: Option<ServerConfig>,
+    #[serde(default)]
+    pub servers: Vec<ServerConfig>,
     pub datapoints: Vec<DatapointConfig>,
     #[serde(default = "default_scan_interval")]
     pub scan_interval_ms: u64,
+    /// Number of samples kept per datapoint for the trend and chart views
+    #[serde(default = "default_history_size")]
+    pub history_size: usize,
+    /// File the event log is kept in; in memory only if not set
+    #[serde(default)]
+    pub event_log: Option<String>,
+    /// Publish datapoint values to an MQTT broker
+    #[serde(default)]
+    pub mqtt: Option<MqttConfig>,
 }
 
 fn default_scan_interval() -> u64 {
     1000
 }
 
+fn default_history_size() -> usize {
+    3600
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MqttConfig {
+    /// Broker address
+    pub host: String,
+    #[serde(default = "default_mqtt_port")]
+    pub port: u16,
+    #[serde(default = "default_mqtt_client_id")]
+    pub client_id: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Topic per datapoint; `{server}` and `{name}` are replaced
+    #[serde(default = "default_mqtt_topic")]
+    pub topic: String,
+    #[serde(default)]
+    pub qos: u8,
+    #[serde(default)]
+    pub retain: bool,
+    #[serde(default)]
+    pub publish: PublishMode,
+}
+
+/// When a datapoint is published.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PublishMode {
+    /// When the value or the error changes
+    #[default]
+    Change,
+    EveryScan,
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_mqtt_client_id() -> String {
+    "datapoint_tui".to_string()
+}
+
+fn default_mqtt_topic() -> String {
+    "datapoint_tui/{server}/{name}".to_string()
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ServerConfig {
+    /// Name referenced by `DatapointConfig::server`
+    #[serde(default = "default_server_name")]
+    pub name: String,
     pub protocol: String,
+    #[serde(default)]
+    pub transport: Transport,
+    /// TCP transport: server address
+    #[serde(default)]
     pub host: String,
+    #[serde(default = "default_port")]
     pub port: u16,
+    /// RTU transport: serial device, e.g. /dev/ttyUSB0
+    #[serde(default)]
+    pub device: Option<String>,
+    #[serde(default = "default_baud_rate")]
+    pub baud_rate: u32,
+    #[serde(default)]
+    pub parity: Parity,
+    #[serde(default = "default_data_bits")]
+    pub data_bits: u8,
+    #[serde(default = "default_stop_bits")]
+    pub stop_bits: u8,
+    /// RTU transport: silent time between frames, 3.5 character times if unset
+    #[serde(default)]
+    pub inter_frame_delay_ms: Option<u64>,
     #[serde(default = "default_unit_id")]
     pub unit_id: u8,
     #[serde(default = "default_endianness")]
     pub endianness: Endianness,
+    /// Delay before the first reconnect attempt, doubled after each failure
+    #[serde(default = "default_reconnect_delay")]
+    pub reconnect_delay_ms: u64,
+    #[serde(default = "default_max_reconnect_delay")]
+    pub max_reconnect_delay_ms: u64,
+    /// Largest number of unconfigured registers read to merge two datapoints
+    #[serde(default)]
+    pub max_read_gap: u16,
+    /// Upper bound for a batched read in registers or bits (1 disables batching)
+    #[serde(default = "default_max_read_block")]
+    pub max_read_block: u16,
+    /// Toggle bitfield bits with FC22 Mask Write Register instead of
+    /// reading the register and writing it back
+    #[serde(default)]
+    pub mask_write: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_server_name() -> String {
+    "default".to_string()
+}
+
+fn default_port() -> u16 {
+    502
+}
+
+fn default_baud_rate() -> u32 {
+    9600
+}
+
+fn default_data_bits() -> u8 {
+    8
+}
+
+fn default_stop_bits() -> u8 {
+    1
 }
 
 fn default_unit_id() -> u8 {
     1
 }
 
+fn default_reconnect_delay() -> u64 {
+    1000
+}
+
+fn default_max_reconnect_delay() -> u64 {
+    30000
+}
+
+fn default_max_read_block() -> u16 {
+    crate::read_plan::MAX_REGISTERS_PER_READ
+}
+
 fn default_endianness() -> Endianness {
     Endianness::Big
 }
@@ -37,6 +173,68 @@ fn default_data_type() -> DataType {
     DataType::U16
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    #[default]
+    Tcp,
+    Rtu,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Parity {
+    #[default]
+    None,
+    Even,
+    Odd,
+}
+
+impl ServerConfig {
+    /// Short connection description for the header, e.g. `modbus://10.0.0.5:502`
+    /// or `modbus-rtu:///dev/ttyUSB0 9600 8N1`.
+    pub fn describe(&self) -> String {
+        match self.transport {
+            Transport::Tcp => format!("{}://{}:{}", self.protocol, self.host, self.port),
+            Transport::Rtu => {
+                let parity = match self.parity {
+                    Parity::None => 'N',
+                    Parity::Even => 'E',
+                    Parity::Odd => 'O',
+                };
+                format!(
+                    "{}-rtu://{} {} {}{}{}",
+                    self.protocol,
+                    self.device.as_deref().unwrap_or("-"),
+                    self.baud_rate,
+                    self.data_bits,
+                    parity,
+                    self.stop_bits
+                )
+            }
+        }
+    }
+
+    /// Silent interval to keep between two requests. Zero for TCP.
+    pub fn inter_frame_delay(&self) -> Duration {
+        if self.transport != Transport::Rtu {
+            return Duration::ZERO;
+        }
+        if let Some(ms) = self.inter_frame_delay_ms {
+            return Duration::from_millis(ms);
+        }
+        // The spec fixes the delay at 1.75ms above 19200 baud
+        if self.baud_rate > 19200 {
+            return Duration::from_micros(1750);
+        }
+        let char_bits = 1
+            + self.data_bits as u64
+            + self.stop_bits as u64
+            + (self.parity != Parity::None) as u64;
+        Duration::from_micros(char_bits * 3_500_000 / self.baud_rate.max(1) as u64)
+    }
+}
+
 #[derive(Debug, Clone, Copy, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Endianness {
@@ -44,6 +242,32 @@ pub enum Endianness {
     Little,
 }
 
+impl Endianness {
+    /// Byte order used for datapoints without their own `byte_order`.
+    /// `little` only swaps the register order, as it always has.
+    pub fn byte_order(self) -> ByteOrder {
+        match self {
+            Endianness::Big => ByteOrder::Abcd,
+            Endianness::Little => ByteOrder::Cdab,
+        }
+    }
+}
+
+/// Order of the bytes of a multi-register value on the wire, see
+/// [`crate::codec`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ByteOrder {
+    #[serde(alias = "abcd")]
+    Abcd,
+    #[serde(alias = "cdab")]
+    Cdab,
+    #[serde(alias = "badc")]
+    Badc,
+    #[serde(alias = "dcba")]
+    Dcba,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct DatapointConfig {
     pub name: String,
@@ -57,28 +281,299 @@ pub struct DatapointConfig {
     pub bitfields: Option<Vec<BitfieldConfig>>,
     #[serde(default = "default_register_type")]
     pub register_type: RegisterType,
+    /// Allow writing this datapoint from the UI (holding registers and coils)
+    #[serde(default)]
+    pub writable: bool,
+    /// Server name; may be omitted when only one server is configured
+    #[serde(default)]
+    pub server: Option<String>,
+    /// Overrides the server's unit ID, e.g. for gateways fronting several slaves
+    #[serde(default)]
+    pub unit_id: Option<u8>,
+    /// Gain applied to the decoded value: `value * scale + offset`
+    #[serde(default)]
+    pub scale: Option<f64>,
+    #[serde(default)]
+    pub offset: Option<f64>,
+    /// Engineering unit shown after the value, e.g. "kW"
+    #[serde(default)]
+    pub unit: Option<String>,
+    /// Decimal places shown; derived from `scale` and `offset` when omitted
+    #[serde(default)]
+    pub decimals: Option<usize>,
+    /// Overrides the server's `endianness`
+    #[serde(default)]
+    pub byte_order: Option<ByteOrder>,
+    /// Labels for coded values, e.g. operating states
+    #[serde(default, rename = "enum")]
+    pub enum_labels: BTreeMap<i64, EnumLabel>,
+    /// Rules checked against every new value
+    #[serde(default)]
+    pub alarms: Vec<AlarmRule>,
+    /// Value served by the `simulate` subcommand
+    #[serde(default)]
+    pub simulate: Option<Simulation>,
+}
+
+impl DatapointConfig {
+    /// Strings default to ABCD (first character in the high byte of the
+    /// first register) regardless of the server's `endianness`.
+    pub fn byte_order(&self, server: &ServerConfig) -> ByteOrder {
+        match self.byte_order {
+            Some(order) => order,
+            None if self.data_type == DataType::String => ByteOrder::Abcd,
+            None => server.endianness.byte_order(),
+        }
+    }
 }
 
 fn default_register_type() -> RegisterType {
     RegisterType::Holding
 }
 
-#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
-#[serde(rename_all = "lowercase")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum RegisterType {
-    Holding, // Function code 3
-    Input,   // Function code 4
+    Holding,       // Function code 3
+    Input,         // Function code 4
+    Coil,          // Function code 1
+    DiscreteInput, // Function code 2
+}
+
+impl RegisterType {
+    /// Coils and discrete inputs address single bits instead of 16-bit words.
+    pub fn is_bit(&self) -> bool {
+        matches!(self, RegisterType::Coil | RegisterType::DiscreteInput)
+    }
+
+    pub fn is_writable(&self) -> bool {
+        matches!(self, RegisterType::Holding | RegisterType::Coil)
+    }
+
+    /// Modbus limit for the quantity of a single read request.
+    pub fn max_read_quantity(&self) -> u16 {
+        if self.is_bit() { 2000 } else { 125 }
+    }
 }
 
+/// Label of one value of an `enum` map, either plain text or
+/// `{ label: ..., color: ... }`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum EnumLabel {
+    Text(String),
+    Styled {
+        label: String,
+        /// Color name ("red", "lightgreen", ...) or "#rrggbb"
+        #[serde(default)]
+        color: Option<String>,
+    },
+}
+
+impl EnumLabel {
+    pub fn label(&self) -> &str {
+        match self {
+            EnumLabel::Text(label) | EnumLabel::Styled { label, .. } => label,
+        }
+    }
+
+    pub fn color(&self) -> Option<&str> {
+        match self {
+            EnumLabel::Text(_) => None,
+            EnumLabel::Styled { color, .. } => color.as_deref(),
+        }
+    }
+}
+
+/// Named flag (`bit`) or multi-bit field (`bits: [start, width]`) of a
+/// bitfield register.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct BitfieldConfig {
-    pub bit: u8,
+    #[serde(default)]
+    pub bit: Option<u8>,
+    #[serde(default)]
+    pub bits: Option<[u8; 2]>,
     pub name: String,
     #[serde(default)]
     pub description: Option<String>,
+    /// Prevent toggling this bit from the UI (e.g. status flags)
+    #[serde(default)]
+    pub read_only: bool,
+    /// Labels for the values of a multi-bit field
+    #[serde(default, rename = "enum")]
+    pub enum_labels: BTreeMap<i64, EnumLabel>,
+    /// Record changes in the event log; turn off for heartbeat bits
+    #[serde(default = "default_true")]
+    pub log: bool,
+}
+
+impl BitfieldConfig {
+    /// First bit and number of bits covered.
+    pub fn span(&self) -> (u8, u8) {
+        match (self.bit, self.bits) {
+            (Some(bit), _) => (bit, 1),
+            (None, Some([start, width])) => (start, width),
+            (None, None) => (0, 1),
+        }
+    }
+}
+
+/// Alarm rule of a datapoint, e.g.
+/// `{ when: high, limit: 80, hysteresis: 2, severity: warning }`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AlarmRule {
+    #[serde(flatten)]
+    pub condition: AlarmCondition,
+    /// Defaults to critical for `high_high`/`low_low`, warning otherwise
+    #[serde(default)]
+    pub severity: Option<Severity>,
+    /// Distance a value must move back inside a limit before the alarm clears
+    #[serde(default)]
+    pub hysteresis: f64,
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+impl AlarmRule {
+    pub fn severity(&self) -> Severity {
+        self.severity.unwrap_or(match self.condition {
+            AlarmCondition::HighHigh { .. } | AlarmCondition::LowLow { .. } => Severity::Critical,
+            _ => Severity::Warning,
+        })
+    }
+}
+
+/// Limits compare the scaled value; `equals`/`not_equals` compare the raw
+/// code of enum datapoints.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "when", rename_all = "snake_case")]
+pub enum AlarmCondition {
+    High { limit: f64 },
+    HighHigh { limit: f64 },
+    Low { limit: f64 },
+    LowLow { limit: f64 },
+    Equals { value: AlarmValue },
+    NotEquals { value: AlarmValue },
+    BitSet { bit: u8 },
+    BitCleared { bit: u8 },
+}
+
+/// Code or enum label an `equals`/`not_equals` alarm compares against.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum AlarmValue {
+    Code(i64),
+    Label(String),
+}
+
+impl fmt::Display for AlarmValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AlarmValue::Code(code) => write!(f, "{}", code),
+            AlarmValue::Label(label) => write!(f, "{}", label),
+        }
+    }
+}
+
+impl AlarmValue {
+    /// The code, looking labels up case-insensitively in `labels`.
+    pub fn resolve(&self, labels: &BTreeMap<i64, EnumLabel>) -> Option<i64> {
+        match self {
+            AlarmValue::Code(code) => Some(*code),
+            AlarmValue::Label(text) => labels
+                .iter()
+                .find(|(_, label)| label.label().eq_ignore_ascii_case(text))
+                .map(|(code, _)| *code),
+        }
+    }
+}
+
+/// Simulated value of a datapoint, e.g.
+/// `{ generator: sine, min: 15, max: 25, period_s: 60 }`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Simulation {
+    #[serde(flatten)]
+    pub generator: Generator,
+    /// Initial value: a number in engineering units, an enum label, text or
+    /// `on`/`off`
+    #[serde(default)]
+    pub value: Option<SimulatedValue>,
+}
+
+/// How a simulated value changes over time. Limits are in engineering units.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "generator", rename_all = "snake_case")]
+pub enum Generator {
+    /// Keeps `value` until it is written
+    Constant,
+    /// Rises from `min` to `max` every period, then starts over
+    Ramp {
+        min: f64,
+        max: f64,
+        #[serde(default = "default_period")]
+        period_s: f64,
+    },
+    Sine {
+        min: f64,
+        max: f64,
+        #[serde(default = "default_period")]
+        period_s: f64,
+    },
+    /// Moves by up to `step` per second, staying between `min` and `max`
+    RandomWalk {
+        min: f64,
+        max: f64,
+        #[serde(default = "default_step")]
+        step: f64,
+    },
+    /// Inverts bit `bit` of a bitfield, or coil `address + bit`, every period
+    Toggle {
+        #[serde(default)]
+        bit: u8,
+        #[serde(default = "default_toggle_period")]
+        period_s: f64,
+    },
+}
+
+fn default_period() -> f64 {
+    60.0
+}
+
+fn default_step() -> f64 {
+    1.0
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+fn default_toggle_period() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum SimulatedValue {
+    Bool(bool),
+    Number(f64),
+    Text(String),
+}
+
+impl fmt::Display for SimulatedValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SimulatedValue::Bool(value) => write!(f, "{}", value),
+            SimulatedValue::Number(value) => write!(f, "{}", value),
+            SimulatedValue::Text(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum DataType {
     #[default]
@@ -87,7 +582,32 @@ pub enum DataType {
     U32,
     I32,
     F32,
+    U64,
+    I64,
+    F64,
     Bitfield,
+    /// Text packed two bytes per register over `length` registers
+    String,
+}
+
+impl DataType {
+    /// Number of registers the value occupies (the minimum for strings).
+    pub fn register_count(self) -> u16 {
+        match self {
+            DataType::U16 | DataType::I16 | DataType::Bitfield | DataType::String => 1,
+            DataType::U32 | DataType::I32 | DataType::F32 => 2,
+            DataType::U64 | DataType::I64 | DataType::F64 => 4,
+        }
+    }
+
+    /// Whether scaling and numeric display apply.
+    pub fn is_numeric(self) -> bool {
+        !matches!(self, DataType::Bitfield | DataType::String)
+    }
+
+    pub fn is_integer(self) -> bool {
+        self.is_numeric() && !matches!(self, DataType::F32 | DataType::F64)
+    }
 }
 
 impl Config {
@@ -96,6 +616,383 @@ impl Config {
             fs::read_to_string(path.as_ref()).context("Failed to read configuration file")?;
         let config: Config =
             serde_yaml::from_str(&content).context("Failed to parse configuration file")?;
-        Ok(config)
+        config.prepare()
+    }
+
+    /// Builds a configuration from its JSON form, e.g. for ad-hoc reads.
+    pub fn from_json(value: serde_json::Value) -> Result<Self> {
+        let config: Config = serde_json::from_value(value).context("Invalid configuration")?;
+        config.prepare()
+    }
+
+    fn prepare(mut self) -> Result<Self> {
+        if let Some(server) = self.server.take() {
+            self.servers.insert(0, server);
+        }
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// Datapoints polled from the named server.
+    pub fn datapoints_for(&self, server: &str) -> Vec<DatapointConfig> {
+        let default = self.servers.first().map(|s| s.name.as_str());
+        self.datapoints
+            .iter()
+            .filter(|dp| dp.server.as_deref().or(default) == Some(server))
+            .cloned()
+            .collect()
+    }
+
+    /// Datapoint names in table order as used for recording columns,
+    /// prefixed with the server name when there are several servers.
+    pub fn column_names(&self) -> Vec<String> {
+        let multi_server = self.servers.len() > 1;
+        self.servers
+            .iter()
+            .flat_map(|server| {
+                self.datapoints_for(&server.name)
+                    .into_iter()
+                    .map(move |dp| {
+                        if multi_server {
+                            format!("{}/{}", server.name, dp.name)
+                        } else {
+                            dp.name
+                        }
+                    })
+            })
+            .collect()
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.servers.is_empty() {
+            anyhow::bail!("No server configured, add a `server` or `servers` section");
+        }
+
+        for (i, server) in self.servers.iter().enumerate() {
+            if self.servers[..i].iter().any(|s| s.name == server.name) {
+                anyhow::bail!("Duplicate server name '{}'", server.name);
+            }
+            match server.transport {
+                Transport::Tcp if server.host.is_empty() => {
+                    anyhow::bail!("{}: host is required for the tcp transport", server.name)
+                }
+                Transport::Rtu if server.device.is_none() => {
+                    anyhow::bail!("{}: device is required for the rtu transport", server.name)
+                }
+                _ => {}
+            }
+            if !(5..=8).contains(&server.data_bits) {
+                anyhow::bail!("{}: data_bits must be between 5 and 8", server.name);
+            }
+            if !(1..=2).contains(&server.stop_bits) {
+                anyhow::bail!("{}: stop_bits must be 1 or 2", server.name);
+            }
+        }
+
+        if let Some(ref mqtt) = self.mqtt {
+            if mqtt.host.is_empty() {
+                anyhow::bail!("mqtt: host is required");
+            }
+            if mqtt.qos > 2 {
+                anyhow::bail!("mqtt: qos must be 0, 1 or 2");
+            }
+            if mqtt.topic.is_empty() || mqtt.topic.contains(['+', '#']) {
+                anyhow::bail!("mqtt: topic must not be empty or contain wildcards");
+            }
+        }
+
+        for dp in &self.datapoints {
+            match dp.server {
+                Some(ref name) if !self.servers.iter().any(|s| &s.name == name) => {
+                    anyhow::bail!("{}: unknown server '{}'", dp.name, name)
+                }
+                None if self.servers.len() > 1 => anyhow::bail!(
+                    "{}: `server` is required when several servers are configured",
+                    dp.name
+                ),
+                _ => {}
+            }
+            if !dp.register_type.is_bit() && dp.length < dp.data_type.register_count() {
+                anyhow::bail!(
+                    "{}: {} needs a length of at least {} registers, got {}",
+                    dp.name,
+                    format!("{:?}", dp.data_type).to_lowercase(),
+                    dp.data_type.register_count(),
+                    dp.length
+                );
+            }
+            // Coil and discrete input addresses and lengths count bits
+            if dp.address as u32 + dp.length as u32 > u16::MAX as u32 + 1 {
+                anyhow::bail!(
+                    "{}: a length of {} at address {} runs past address 65535",
+                    dp.name,
+                    dp.length,
+                    dp.address
+                );
+            }
+            if dp.data_type == DataType::Bitfield && !dp.register_type.is_bit() && dp.length > 2 {
+                anyhow::bail!(
+                    "{}: bitfield needs a length of 1 or 2 registers, got {}",
+                    dp.name,
+                    dp.length
+                );
+            }
+            if let Some(ref bitfields) = dp.bitfields {
+                // Bit numbers are u8, so fields of long coil arrays must end
+                // by bit 255
+                let capacity = if dp.register_type.is_bit() {
+                    (dp.length as u32).min(u8::MAX as u32 + 1)
+                } else {
+                    dp.length as u32 * 16
+                };
+                for bf in bitfields {
+                    if bf.bit.is_some() == bf.bits.is_some() {
+                        anyhow::bail!(
+                            "{}: bitfield '{}' needs either `bit` or `bits: [start, width]`",
+                            dp.name,
+                            bf.name
+                        );
+                    }
+                    let (start, width) = bf.span();
+                    if width == 0 || start as u32 + width as u32 > capacity {
+                        anyhow::bail!(
+                            "{}: bitfield '{}' does not fit in {} bits",
+                            dp.name,
+                            bf.name,
+                            capacity
+                        );
+                    }
+                }
+            }
+            if !dp.enum_labels.is_empty() {
+                if dp.register_type.is_bit() || !dp.data_type.is_integer() {
+                    anyhow::bail!("{}: enum only applies to integer data types", dp.name);
+                }
+                for label in dp.enum_labels.values() {
+                    if let Some(color) = label.color()
+                        && color.parse::<ratatui::style::Color>().is_err()
+                    {
+                        anyhow::bail!("{}: unknown color '{}'", dp.name, color);
+                    }
+                }
+            }
+            if dp.scale.is_some() || dp.offset.is_some() {
+                if dp.register_type.is_bit() || !dp.data_type.is_numeric() {
+                    anyhow::bail!(
+                        "{}: scale and offset only apply to numeric data types",
+                        dp.name
+                    );
+                }
+                if dp.scale == Some(0.0) {
+                    anyhow::bail!("{}: scale must not be zero", dp.name);
+                }
+            }
+            for rule in &dp.alarms {
+                Self::validate_alarm(dp, rule)?;
+            }
+            if let Some(ref simulation) = dp.simulate {
+                Self::validate_simulation(dp, simulation)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_simulation(dp: &DatapointConfig, simulation: &Simulation) -> Result<()> {
+        match simulation.generator {
+            Generator::Constant => {}
+            Generator::Ramp { min, max, period_s } | Generator::Sine { min, max, period_s } => {
+                Self::validate_range(dp, min, max)?;
+                if period_s <= 0.0 {
+                    anyhow::bail!("{}: simulation period_s must be positive", dp.name);
+                }
+            }
+            Generator::RandomWalk { min, max, step } => {
+                Self::validate_range(dp, min, max)?;
+                if step <= 0.0 {
+                    anyhow::bail!("{}: simulation step must be positive", dp.name);
+                }
+            }
+            Generator::Toggle { bit, period_s } => {
+                let capacity = if dp.register_type.is_bit() {
+                    dp.length as u32
+                } else if dp.data_type == DataType::Bitfield {
+                    dp.length as u32 * 16
+                } else {
+                    anyhow::bail!(
+                        "{}: toggle only applies to bitfields, coils and discrete inputs",
+                        dp.name
+                    );
+                };
+                if bit as u32 >= capacity {
+                    anyhow::bail!(
+                        "{}: toggle bit {} does not fit in {} bits",
+                        dp.name,
+                        bit,
+                        capacity
+                    );
+                }
+                if period_s <= 0.0 {
+                    anyhow::bail!("{}: simulation period_s must be positive", dp.name);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_range(dp: &DatapointConfig, min: f64, max: f64) -> Result<()> {
+        if dp.register_type.is_bit() || !dp.data_type.is_numeric() {
+            anyhow::bail!(
+                "{}: ramp, sine and random_walk only apply to numeric data types",
+                dp.name
+            );
+        }
+        if min > max {
+            anyhow::bail!("{}: simulation min must not be above max", dp.name);
+        }
+        Ok(())
+    }
+
+    fn validate_alarm(dp: &DatapointConfig, rule: &AlarmRule) -> Result<()> {
+        if rule.hysteresis < 0.0 {
+            anyhow::bail!("{}: alarm hysteresis must not be negative", dp.name);
+        }
+        match rule.condition {
+            AlarmCondition::High { .. }
+            | AlarmCondition::HighHigh { .. }
+            | AlarmCondition::Low { .. }
+            | AlarmCondition::LowLow { .. } => {
+                if dp.register_type.is_bit() || !dp.data_type.is_numeric() {
+                    anyhow::bail!("{}: limit alarms only apply to numeric data types", dp.name);
+                }
+            }
+            AlarmCondition::Equals { ref value } | AlarmCondition::NotEquals { ref value } => {
+                if dp.register_type.is_bit() || !dp.data_type.is_integer() {
+                    anyhow::bail!(
+                        "{}: equals/not_equals alarms only apply to integer data types",
+                        dp.name
+                    );
+                }
+                if let AlarmValue::Label(ref label) = *value
+                    && value.resolve(&dp.enum_labels).is_none()
+                {
+                    anyhow::bail!("{}: alarm value '{}' is not in the enum", dp.name, label);
+                }
+            }
+            AlarmCondition::BitSet { bit } | AlarmCondition::BitCleared { bit } => {
+                let capacity = if dp.register_type.is_bit() {
+                    dp.length as u32
+                } else if dp.data_type == DataType::Bitfield {
+                    dp.length as u32 * 16
+                } else {
+                    anyhow::bail!(
+                        "{}: bit alarms only apply to bitfields, coils and discrete inputs",
+                        dp.name
+                    );
+                };
+                if bit as u32 >= capacity {
+                    anyhow::bail!("{}: alarm bit {} is out of range", dp.name, bit);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+
+    fn server(server: Value) -> ServerConfig {
+        let config = Config::from_json(json!({
+            "server": server,
+            "datapoints": [],
+        }))
+        .unwrap();
+        config.servers[0].clone()
+    }
+
+    fn rtu(baud_rate: u32, parity: &str, stop_bits: u8) -> ServerConfig {
+        server(json!({
+            "protocol": "modbus", "transport": "rtu", "device": "/dev/ttyUSB0",
+            "baud_rate": baud_rate, "parity": parity, "stop_bits": stop_bits,
+        }))
+    }
+
+    #[test]
+    fn inter_frame_delay_is_three_and_a_half_characters() {
+        // Characters of 10 bits (8N1) and 11 bits (8E1, 8N2)
+        let cases = [
+            (1200, "none", 1, 29_166),
+            (9600, "none", 1, 3_645),
+            (9600, "even", 1, 4_010),
+            (4800, "none", 2, 8_020),
+            (19200, "odd", 1, 2_005),
+            // Fixed above 19200 baud
+            (38400, "none", 1, 1_750),
+            (115200, "even", 1, 1_750),
+        ];
+        for (baud_rate, parity, stop_bits, micros) in cases {
+            assert_eq!(
+                rtu(baud_rate, parity, stop_bits).inter_frame_delay(),
+                Duration::from_micros(micros),
+                "{} {} {}",
+                baud_rate,
+                parity,
+                stop_bits
+            );
+        }
+    }
+
+    #[test]
+    fn inter_frame_delay_override() {
+        let mut serial = rtu(9600, "none", 1);
+        serial.inter_frame_delay_ms = Some(20);
+        assert_eq!(serial.inter_frame_delay(), Duration::from_millis(20));
+        serial.inter_frame_delay_ms = Some(0);
+        assert_eq!(serial.inter_frame_delay(), Duration::ZERO);
+
+        let tcp = server(json!({ "protocol": "modbus", "host": "10.0.0.5" }));
+        assert_eq!(tcp.inter_frame_delay(), Duration::ZERO);
+    }
+
+    #[test]
+    fn describes_the_connection() {
+        assert_eq!(
+            rtu(19200, "even", 1).describe(),
+            "modbus-rtu:///dev/ttyUSB0 19200 8E1"
+        );
+        let tcp = server(json!({ "protocol": "modbus", "host": "10.0.0.5" }));
+        assert_eq!(tcp.describe(), "modbus://10.0.0.5:502");
+    }
+
+    #[test]
+    fn datapoints_end_by_address_65535() {
+        let load = |datapoint: Value| {
+            Config::from_json(json!({
+                "server": { "protocol": "modbus", "host": "10.0.0.5" },
+                "datapoints": [datapoint],
+            }))
+        };
+        assert!(load(json!({ "name": "last", "address": 65535, "length": 1 })).is_ok());
+        assert!(
+            load(json!({ "name": "coils", "address": 65528, "length": 8,
+                         "register_type": "coil" }))
+            .is_ok()
+        );
+
+        let error = load(json!({ "name": "wide", "address": 65535, "length": 2,
+                                 "data_type": "u32" }))
+        .err()
+        .unwrap();
+        assert_eq!(
+            format!("{:#}", error),
+            "wide: a length of 2 at address 65535 runs past address 65535"
+        );
+        let error = load(json!({ "name": "inputs", "address": 65530, "length": 8,
+                                 "register_type": "discrete_input" }))
+        .err()
+        .unwrap();
+        assert!(format!("{:#}", error).contains("runs past"), "{:#}", error);
     }
 }
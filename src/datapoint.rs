@@ -1,17 +1,70 @@
+use crate::alarm::Alarm;
+use crate::config::{BitfieldConfig, DataType, EnumLabel, RegisterType};
+use crate::events::{Event, EventKind};
+use crate::history::History;
+use anyhow::{Context, Result};
 use chrono::{DateTime, Local};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashSet};
 use std::fmt;
 
 #[derive(Debug, Clone)]
 pub struct Datapoint {
     pub name: String,
+    /// Name of the server this datapoint is read from
+    pub server: String,
+    pub unit_id: u8,
     pub address: u16,
     pub value: Option<DataValue>,
     pub last_updated: Option<DateTime<Local>>,
     pub error: Option<String>,
     #[allow(dead_code)]
     pub description: Option<String>,
-    pub bitfield_names: Option<HashMap<u8, String>>,
+    pub bitfields: Option<Vec<BitfieldConfig>>,
+    pub data_type: DataType,
+    pub register_type: RegisterType,
+    pub length: u16,
+    pub writable: bool,
+    pub read_only_bits: HashSet<u8>,
+    pub write_status: Option<WriteStatus>,
+    pub history: History,
+    /// Register words of the last successful read
+    pub registers: Vec<u16>,
+    pub scale: f64,
+    pub offset: f64,
+    pub unit: Option<String>,
+    pub decimals: Option<usize>,
+    pub enum_labels: BTreeMap<i64, EnumLabel>,
+    pub alarms: Vec<Alarm>,
+}
+
+/// Current state of one configured flag or multi-bit field.
+#[derive(Debug, Clone)]
+pub struct BitStatus {
+    /// First bit of the field
+    pub bit: u8,
+    pub width: u8,
+    pub name: String,
+    pub value: u32,
+    /// Enum label of the value; `Some(None)` if it is missing from the map
+    pub label: Option<Option<EnumLabel>>,
+}
+
+impl BitStatus {
+    pub fn is_flag(&self) -> bool {
+        self.width == 1
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.value != 0
+    }
+}
+
+/// Outcome of the last write issued from the UI.
+#[derive(Debug, Clone)]
+pub struct WriteStatus {
+    pub value: String,
+    pub error: Option<String>,
+    pub timestamp: DateTime<Local>,
 }
 
 #[derive(Debug, Clone)]
@@ -21,10 +74,101 @@ pub enum DataValue {
     U32(u32),
     I32(i32),
     F32(f32),
+    U64(u64),
+    I64(i64),
+    F64(f64),
     Bitfield(u16),
+    /// Bitfield spanning two registers
+    Bitfield32(u32),
+    String(String),
+    /// A single coil or discrete input
+    Bool(bool),
+    /// Consecutive coils or discrete inputs, lowest address first
+    Bits(Vec<bool>),
 }
 
+/// Coils beyond this are not shown in the timeline (and not kept in the
+/// history, which packs them into an `f64`).
+pub const MAX_TIMELINE_BITS: u16 = 32;
+
 impl DataValue {
+    /// Numeric value recorded in the history. Coil arrays are packed into
+    /// an integer, lowest address in bit 0.
+    pub fn as_f64(&self) -> Option<f64> {
+        let value = match self {
+            DataValue::U16(v) => *v as f64,
+            DataValue::I16(v) => *v as f64,
+            DataValue::U32(v) => *v as f64,
+            DataValue::I32(v) => *v as f64,
+            DataValue::F32(v) => *v as f64,
+            DataValue::U64(v) => *v as f64,
+            DataValue::I64(v) => *v as f64,
+            DataValue::F64(v) => *v,
+            DataValue::Bitfield(v) => *v as f64,
+            DataValue::Bitfield32(v) => *v as f64,
+            DataValue::String(_) => return None,
+            DataValue::Bool(v) => *v as u8 as f64,
+            DataValue::Bits(bits) => {
+                bits.iter()
+                    .take(MAX_TIMELINE_BITS as usize)
+                    .enumerate()
+                    .fold(0u64, |acc, (i, bit)| acc | ((*bit as u64) << i)) as f64
+            }
+        };
+        Some(value)
+    }
+
+    /// Integer value for enum lookups.
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            DataValue::U16(v) => Some(v as i64),
+            DataValue::I16(v) => Some(v as i64),
+            DataValue::U32(v) => Some(v as i64),
+            DataValue::I32(v) => Some(v as i64),
+            DataValue::U64(v) => i64::try_from(v).ok(),
+            DataValue::I64(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Whether the value is a number that scaling applies to.
+    pub fn is_numeric(&self) -> bool {
+        !matches!(
+            self,
+            DataValue::Bitfield(_)
+                | DataValue::Bitfield32(_)
+                | DataValue::String(_)
+                | DataValue::Bool(_)
+                | DataValue::Bits(_)
+        )
+    }
+
+    /// Converts a raw value computed from engineering units back into the
+    /// wire type, rounding to the nearest integer for integer types.
+    pub fn from_f64(data_type: DataType, raw: f64) -> Result<Self> {
+        let rounded = raw.round();
+        let check = |min: f64, max: f64| {
+            if rounded < min || rounded > max || !raw.is_finite() {
+                anyhow::bail!("Raw value {} out of range", rounded)
+            }
+            Ok(rounded)
+        };
+        let value = match data_type {
+            DataType::U16 => DataValue::U16(check(0.0, u16::MAX as f64)? as u16),
+            DataType::I16 => DataValue::I16(check(i16::MIN as f64, i16::MAX as f64)? as i16),
+            DataType::U32 => DataValue::U32(check(0.0, u32::MAX as f64)? as u32),
+            DataType::I32 => DataValue::I32(check(i32::MIN as f64, i32::MAX as f64)? as i32),
+            DataType::F32 => DataValue::F32(raw as f32),
+            DataType::U64 => DataValue::U64(check(0.0, u64::MAX as f64)? as u64),
+            DataType::I64 => DataValue::I64(check(i64::MIN as f64, i64::MAX as f64)? as i64),
+            DataType::F64 => DataValue::F64(raw),
+            DataType::Bitfield | DataType::String => {
+                anyhow::bail!("{:?} values cannot be scaled", data_type)
+            }
+        };
+        Ok(value)
+    }
+
     pub fn type_name(&self) -> &str {
         match self {
             DataValue::U16(_) => "u16",
@@ -32,9 +176,90 @@ impl DataValue {
             DataValue::U32(_) => "u32",
             DataValue::I32(_) => "i32",
             DataValue::F32(_) => "f32",
+            DataValue::U64(_) => "u64",
+            DataValue::I64(_) => "i64",
+            DataValue::F64(_) => "f64",
             DataValue::Bitfield(_) => "bits",
+            DataValue::Bitfield32(_) => "bits32",
+            DataValue::String(_) => "string",
+            DataValue::Bool(_) => "bool",
+            DataValue::Bits(_) => "coils",
         }
     }
+
+    /// Parses user input for coils: `on`/`off`/`1`/`0`/`true`/`false` for a
+    /// single coil, or a string of `0`/`1` (lowest address first) of
+    /// `length` characters for several.
+    pub fn parse_bits(input: &str, length: usize) -> Result<Self> {
+        let input = input.trim();
+        if length <= 1 {
+            let value = match input.to_lowercase().as_str() {
+                "1" | "on" | "true" => true,
+                "0" | "off" | "false" => false,
+                _ => anyhow::bail!("Expected ON or OFF"),
+            };
+            return Ok(DataValue::Bool(value));
+        }
+
+        let bits: Vec<bool> = input
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| match c {
+                '1' => Ok(true),
+                '0' => Ok(false),
+                _ => Err(anyhow::anyhow!("Expected a string of 0 and 1")),
+            })
+            .collect::<Result<_>>()?;
+        if bits.len() != length {
+            anyhow::bail!("Expected {} bits, got {}", length, bits.len());
+        }
+        Ok(DataValue::Bits(bits))
+    }
+
+    /// Parses user input into a value of the given type. Unsigned types
+    /// and bitfields also accept `0x` prefixed hex.
+    pub fn parse(data_type: DataType, input: &str) -> Result<Self> {
+        let input = input.trim();
+        let value = match data_type {
+            DataType::U16 => DataValue::U16(parse_unsigned(input)?),
+            DataType::Bitfield => DataValue::Bitfield(parse_unsigned(input)?),
+            DataType::String => DataValue::String(input.to_string()),
+            DataType::I16 => DataValue::I16(input.parse().context("Expected an i16 value")?),
+            DataType::U32 => DataValue::U32(parse_unsigned(input)?),
+            DataType::I32 => DataValue::I32(input.parse().context("Expected an i32 value")?),
+            DataType::F32 => DataValue::F32(input.parse().context("Expected an f32 value")?),
+            DataType::U64 => DataValue::U64(parse_unsigned(input)?),
+            DataType::I64 => DataValue::I64(input.parse().context("Expected an i64 value")?),
+            DataType::F64 => DataValue::F64(input.parse().context("Expected an f64 value")?),
+        };
+        Ok(value)
+    }
+}
+
+/// Number of decimal places needed to show `value` exactly (up to 6).
+fn decimal_places(value: f64) -> usize {
+    let mut scaled = value.abs();
+    let mut places = 0;
+    while places < 6 && (scaled - scaled.round()).abs() > 1e-9 {
+        scaled *= 10.0;
+        places += 1;
+    }
+    places
+}
+
+fn parse_unsigned<T>(input: &str) -> Result<T>
+where
+    T: TryFrom<u64>,
+{
+    let value = match input
+        .strip_prefix("0x")
+        .or_else(|| input.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => input.parse(),
+    }
+    .context("Expected an unsigned integer")?;
+    T::try_from(value).map_err(|_| anyhow::anyhow!("Value {} out of range", value))
 }
 
 impl fmt::Display for DataValue {
@@ -45,7 +270,19 @@ impl fmt::Display for DataValue {
             DataValue::U32(v) => write!(f, "{}", v),
             DataValue::I32(v) => write!(f, "{}", v),
             DataValue::F32(v) => write!(f, "{:.2}", v),
+            DataValue::U64(v) => write!(f, "{}", v),
+            DataValue::I64(v) => write!(f, "{}", v),
+            DataValue::F64(v) => write!(f, "{:.2}", v),
             DataValue::Bitfield(v) => write!(f, "0x{:04X}", v),
+            DataValue::Bitfield32(v) => write!(f, "0x{:08X}", v),
+            DataValue::String(v) => write!(f, "{}", v),
+            DataValue::Bool(v) => write!(f, "{}", if *v { "ON" } else { "OFF" }),
+            DataValue::Bits(bits) => {
+                for bit in bits {
+                    write!(f, "{}", *bit as u8)?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -54,12 +291,28 @@ impl Datapoint {
     pub fn new(name: String, address: u16, description: Option<String>) -> Self {
         Self {
             name,
+            server: String::new(),
+            unit_id: 1,
             address,
             value: None,
             last_updated: None,
             error: None,
             description,
-            bitfield_names: None,
+            bitfields: None,
+            data_type: DataType::default(),
+            register_type: RegisterType::Holding,
+            length: 1,
+            writable: false,
+            read_only_bits: HashSet::new(),
+            write_status: None,
+            history: History::new(0),
+            registers: Vec::new(),
+            scale: 1.0,
+            offset: 0.0,
+            unit: None,
+            decimals: None,
+            enum_labels: BTreeMap::new(),
+            alarms: Vec::new(),
         }
     }
 
@@ -67,23 +320,263 @@ impl Datapoint {
         name: String,
         address: u16,
         description: Option<String>,
-        bitfield_names: HashMap<u8, String>,
+        bitfields: Vec<BitfieldConfig>,
     ) -> Self {
         Self {
-            name,
-            address,
-            value: None,
-            last_updated: None,
-            error: None,
-            description,
-            bitfield_names: Some(bitfield_names),
+            bitfields: Some(bitfields),
+            ..Self::new(name, address, description)
         }
     }
 
-    pub fn update_value(&mut self, value: DataValue) {
+    /// Stores a value read at `now` and re-evaluates the alarms. Returns
+    /// the alarm and bit transitions for the event log.
+    pub fn update_value(&mut self, value: DataValue, now: DateTime<Local>) -> Vec<Event> {
+        let before = self
+            .value
+            .as_ref()
+            .and_then(|_| self.get_bitfield_status())
+            .unwrap_or_default();
+        if let Some(sample) = self.to_engineering(&value) {
+            self.history.push(now, sample);
+        }
         self.value = Some(value);
-        self.last_updated = Some(Local::now());
+        self.last_updated = Some(now);
         self.error = None;
+
+        let mut events = self.bit_events(&before, now);
+        let mut alarms = std::mem::take(&mut self.alarms);
+        for alarm in alarms.iter_mut() {
+            let Some(raised) = alarm.update(self, now) else {
+                continue;
+            };
+            let kind = if raised {
+                EventKind::AlarmRaised
+            } else {
+                EventKind::AlarmCleared
+            };
+            let mut message = format!(
+                "{} ({})",
+                self.display_value().unwrap_or_default(),
+                alarm.describe()
+            );
+            if raised && let Some(ref text) = alarm.rule.message {
+                message.push_str(&format!(": {}", text));
+            }
+            let mut event = Event::new(kind, &self.server, Some(&self.name), message);
+            event.timestamp = now;
+            event.alarm = Some(alarm.describe());
+            event.severity = Some(alarm.severity());
+            events.push(event);
+        }
+        self.alarms = alarms;
+        events
+    }
+
+    /// Changes of the logged bitfield entries since `before`.
+    fn bit_events(&self, before: &[BitStatus], now: DateTime<Local>) -> Vec<Event> {
+        let Some(ref bitfields) = self.bitfields else {
+            return Vec::new();
+        };
+        let after = self.get_bitfield_status().unwrap_or_default();
+        let mut events = Vec::new();
+        for (old, new) in before.iter().zip(after.iter()) {
+            let logged = bitfields
+                .iter()
+                .any(|bf| bf.log && bf.name == new.name && bf.span().0 == new.bit);
+            if old.value == new.value || !logged {
+                continue;
+            }
+            let message = if new.is_flag() {
+                let state = if new.is_set() { "set" } else { "cleared" };
+                format!("Bit {} {} {}", new.bit, new.name, state)
+            } else {
+                let label = match new.label {
+                    Some(Some(ref label)) => format!(" ({})", label.label()),
+                    Some(None) => " (Unknown)".to_string(),
+                    None => String::new(),
+                };
+                format!("{} {} -> {}{}", new.name, old.value, new.value, label)
+            };
+            let mut event = Event::new(
+                EventKind::BitChanged,
+                &self.server,
+                Some(&self.name),
+                message,
+            );
+            event.timestamp = now;
+            events.push(event);
+        }
+        events
+    }
+
+    /// Highest-severity active alarm.
+    pub fn active_alarm(&self) -> Option<&Alarm> {
+        self.alarms
+            .iter()
+            .filter(|alarm| alarm.is_active())
+            .max_by_key(|alarm| alarm.severity())
+    }
+
+    /// Current value with scale and offset applied.
+    pub fn engineering_value(&self) -> Option<f64> {
+        self.value.as_ref().and_then(|v| self.to_engineering(v))
+    }
+
+    fn is_scaled(&self) -> bool {
+        self.scale != 1.0 || self.offset != 0.0
+    }
+
+    /// Applies scale and offset to a numeric value. Bitfields and coils are
+    /// passed through unchanged.
+    fn to_engineering(&self, value: &DataValue) -> Option<f64> {
+        let raw = value.as_f64()?;
+        if value.is_numeric() {
+            Some(raw * self.scale + self.offset)
+        } else {
+            Some(raw)
+        }
+    }
+
+    /// Looks up the enum label of a value. `Some(None)` means the value is
+    /// missing from the datapoint's `enum` map.
+    pub fn enum_label(&self, value: &DataValue) -> Option<Option<&EnumLabel>> {
+        if self.enum_labels.is_empty() {
+            return None;
+        }
+        Some(self.enum_labels.get(&value.as_i64()?))
+    }
+
+    /// Formats a value in engineering units without the unit, in the form
+    /// [`Datapoint::parse_input`] accepts. Enum values are shown as
+    /// `Running (2)`.
+    pub fn format_number(&self, value: &DataValue) -> String {
+        if let Some(label) = self.enum_label(value) {
+            let code = value.as_i64().unwrap_or_default();
+            return match label {
+                Some(label) => format!("{} ({})", label.label(), code),
+                None => format!("Unknown ({})", code),
+            };
+        }
+        match self.to_engineering(value) {
+            Some(eng) if value.is_numeric() && (self.is_scaled() || self.decimals.is_some()) => {
+                let decimals = self.decimals.unwrap_or_else(|| match value {
+                    DataValue::F32(_) | DataValue::F64(_) => 2,
+                    _ => decimal_places(self.scale).max(decimal_places(self.offset)),
+                });
+                format!("{:.*}", decimals, eng)
+            }
+            _ => value.to_string(),
+        }
+    }
+
+    /// Formats a value in engineering units, e.g. `12.5 kW`.
+    pub fn format_value(&self, value: &DataValue) -> String {
+        let text = self.format_number(value);
+        match self.unit {
+            Some(ref unit) if value.is_numeric() && self.enum_label(value).is_none() => {
+                format!("{} {}", text, unit)
+            }
+            _ => text,
+        }
+    }
+
+    /// Current value in engineering units, or `None` before the first read.
+    pub fn display_value(&self) -> Option<String> {
+        self.value.as_ref().map(|v| self.format_value(v))
+    }
+
+    /// Current value as decoded from the wire, followed by the register words.
+    pub fn raw_display(&self) -> Option<String> {
+        let value = self.value.as_ref()?;
+        if self.registers.is_empty() || self.register_type.is_bit() {
+            return Some(value.to_string());
+        }
+        let words: Vec<String> = self
+            .registers
+            .iter()
+            .map(|w| format!("{:04X}", w))
+            .collect();
+        Some(format!("{} [{}]", value, words.join(" ")))
+    }
+
+    /// Parses a value entered in the UI. Scaled datapoints take the value in
+    /// engineering units (optionally followed by the unit) and convert it
+    /// back to the raw register value.
+    pub fn parse_input(&self, input: &str) -> Result<DataValue> {
+        if self.register_type.is_bit() {
+            return DataValue::parse_bits(input, self.length as usize);
+        }
+        if self.data_type == DataType::String && input.trim().len() > self.length as usize * 2 {
+            anyhow::bail!(
+                "Text is {} bytes, at most {} fit",
+                input.trim().len(),
+                self.length * 2
+            );
+        }
+        if self.data_type == DataType::Bitfield && self.length >= 2 {
+            return Ok(DataValue::Bitfield32(parse_unsigned(input.trim())?));
+        }
+        if let Some(code) = self.parse_enum(input) {
+            return DataValue::parse(self.data_type, &code.to_string());
+        }
+        if !self.is_scaled() {
+            return DataValue::parse(self.data_type, input);
+        }
+
+        let mut input = input.trim();
+        if let Some(ref unit) = self.unit {
+            input = input
+                .strip_suffix(unit.as_str())
+                .unwrap_or(input)
+                .trim_end();
+        }
+        let eng: f64 = input.parse().context("Expected a number")?;
+        DataValue::from_f64(self.data_type, (eng - self.offset) / self.scale)
+    }
+
+    /// Resolves an enum label (case insensitive) or `Label (code)` to the code.
+    fn parse_enum(&self, input: &str) -> Option<i64> {
+        let input = input.trim();
+        if let Some((code, _)) = self
+            .enum_labels
+            .iter()
+            .find(|(_, label)| label.label().eq_ignore_ascii_case(input))
+        {
+            return Some(*code);
+        }
+        let (_, code) = input.strip_suffix(')')?.rsplit_once('(')?;
+        code.trim().parse().ok()
+    }
+
+    /// Whether the history is best shown per bit rather than as a number.
+    pub fn has_bit_timeline(&self) -> bool {
+        self.data_type == DataType::Bitfield || (self.register_type.is_bit() && self.length > 1)
+    }
+
+    /// Bits shown in the timeline with their labels, lowest first. Bits of
+    /// multi-bit fields are shown individually as `name.0`, `name.1`, ...
+    pub fn timeline_bits(&self) -> Vec<(u8, String)> {
+        let mut bits: Vec<(u8, String)> = match self.bitfields {
+            Some(ref bitfields) => bitfields
+                .iter()
+                .flat_map(|bf| {
+                    let (start, width) = bf.span();
+                    (0..width).map(move |i| {
+                        let name = if width == 1 {
+                            bf.name.clone()
+                        } else {
+                            format!("{}.{}", bf.name, i)
+                        };
+                        (start + i, name)
+                    })
+                })
+                .collect(),
+            None => (0..self.length.min(MAX_TIMELINE_BITS) as u8)
+                .map(|b| (b, format!("{}", self.address + b as u16)))
+                .collect(),
+        };
+        bits.sort_by_key(|(bit, _)| *bit);
+        bits
     }
 
     pub fn update_error(&mut self, error: String) {
@@ -91,19 +584,98 @@ impl Datapoint {
         self.last_updated = Some(Local::now());
     }
 
-    pub fn get_bitfield_status(&self) -> Option<Vec<(u8, String, bool)>> {
-        if let (Some(bitfield_names), Some(DataValue::Bitfield(value))) =
-            (&self.bitfield_names, &self.value)
-        {
-            let mut bits = Vec::new();
-            for (bit, name) in bitfield_names {
-                let is_set = (*value & (1 << bit)) != 0;
-                bits.push((*bit, name.clone(), is_set));
+    pub fn update_write_status(&mut self, value: String, error: Option<String>) {
+        self.write_status = Some(WriteStatus {
+            value,
+            error,
+            timestamp: Local::now(),
+        });
+    }
+
+    /// State of one bit of a bitfield, coil or discrete input value.
+    pub fn bit(&self, bit: u8) -> Option<bool> {
+        match self.value {
+            Some(DataValue::Bitfield(value)) => Some(bit < 16 && (value & (1 << bit)) != 0),
+            Some(DataValue::Bitfield32(value)) => Some(bit < 32 && (value & (1 << bit)) != 0),
+            Some(DataValue::Bool(value)) => Some(bit == 0 && value),
+            Some(DataValue::Bits(ref bits)) => Some(bits.get(bit as usize) == Some(&true)),
+            _ => None,
+        }
+    }
+
+    pub fn get_bitfield_status(&self) -> Option<Vec<BitStatus>> {
+        let bitfields = self.bitfields.as_ref()?;
+
+        let mut fields = Vec::new();
+        for bf in bitfields {
+            let (start, width) = bf.span();
+            let mut value = 0u32;
+            for i in 0..width.min(32) {
+                value |= (self.bit(start + i)? as u32) << i;
             }
-            bits.sort_by_key(|(bit, _, _)| *bit);
-            Some(bits)
-        } else {
-            None
+            let label =
+                (!bf.enum_labels.is_empty()).then(|| bf.enum_labels.get(&(value as i64)).cloned());
+            fields.push(BitStatus {
+                bit: start,
+                width,
+                name: bf.name.clone(),
+                value,
+                label,
+            });
         }
+        fields.sort_by_key(|field| field.bit);
+        Some(fields)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::scanner::new_datapoint;
+    use serde_json::{Value, json};
+
+    fn config(datapoint: Value) -> Result<Config> {
+        Config::from_json(json!({
+            "server": { "protocol": "modbus", "host": "127.0.0.1" },
+            "datapoints": [datapoint],
+        }))
+    }
+
+    fn datapoint(datapoint: Value) -> Datapoint {
+        let config = config(datapoint).unwrap();
+        new_datapoint(&config.servers[0], &config.datapoints[0], 1)
+    }
+
+    #[test]
+    fn bitfield32_has_no_unit_or_decimals() {
+        let dp = datapoint(json!({
+            "name": "status", "address": 0, "length": 2, "data_type": "bitfield",
+            "unit": "V", "decimals": 1,
+        }));
+        assert_eq!(
+            dp.format_value(&DataValue::Bitfield32(0x10001)),
+            "0x00010001"
+        );
+    }
+
+    #[test]
+    fn coil_bitfields_end_by_bit_255() {
+        let coils = |bits: [u16; 2]| {
+            json!({
+                "name": "coils", "address": 0, "length": 2000, "register_type": "coil",
+                "bitfields": [{ "name": "field", "bits": bits }],
+            })
+        };
+        assert!(config(coils([250, 10])).is_err());
+
+        let mut dp = datapoint(coils([250, 6]));
+        let mut bits = vec![false; 2000];
+        bits[255] = true;
+        dp.value = Some(DataValue::Bits(bits));
+        let status = dp.get_bitfield_status().unwrap();
+        assert_eq!((status[0].bit, status[0].value), (250, 0b100000));
+        let timeline = dp.timeline_bits();
+        assert_eq!(timeline.last().unwrap(), &(255, "field.5".to_string()));
     }
 }
@@ -0,0 +1,551 @@
+//! `discover` subcommand: finds the registers a device answers for when
+//! there is no reliable register map.
+//!
+//! The address range is read in chunks. A chunk the device rejects with
+//! Illegal Data Address is split in halves until the single registers that
+//! respond are found, so a sweep takes few requests where the map is dense.
+//! Chunks that time out or fail otherwise are counted as a whole.
+
+use anyhow::{Context as _, Result};
+use clap::{Args, ValueEnum};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use tokio::time::{Duration, timeout};
+use tokio_modbus::client::Context;
+use tokio_modbus::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Table {
+    /// Holding registers (FC3)
+    Holding,
+    /// Input registers (FC4)
+    Input,
+}
+
+impl Table {
+    pub fn name(self) -> &'static str {
+        match self {
+            Table::Holding => "holding",
+            Table::Input => "input",
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct DiscoverArgs {
+    /// Modbus TCP server to probe
+    #[arg(long)]
+    host: String,
+
+    #[arg(long, default_value_t = 502)]
+    port: u16,
+
+    #[arg(long, default_value_t = 1)]
+    unit: u8,
+
+    /// First address of the sweep
+    #[arg(long, default_value_t = 0)]
+    start: u16,
+
+    /// Last address of the sweep
+    #[arg(long, default_value_t = 999)]
+    end: u16,
+
+    #[arg(long, value_enum, default_value = "holding")]
+    register_type: Table,
+
+    /// Registers per request
+    #[arg(long, default_value_t = 125, value_parser = clap::value_parser!(u16).range(1..=125))]
+    chunk: u16,
+
+    /// Time to wait for each response
+    #[arg(long, default_value_t = 1000, value_name = "MS")]
+    timeout_ms: u64,
+
+    /// Write a starter config for the found ranges to FILE
+    #[arg(long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Ranges to put in the starter config by number, e.g. 1,3-4 [default: all]
+    #[arg(long, value_name = "LIST", requires = "output")]
+    select: Option<String>,
+}
+
+/// Sweeps the address range, prints the responding ranges and writes the
+/// starter config if asked to.
+pub async fn run(args: &DiscoverArgs) -> Result<()> {
+    if args.start > args.end {
+        anyhow::bail!("--start must not be above --end");
+    }
+    let addr = tokio::net::lookup_host((args.host.as_str(), args.port))
+        .await
+        .with_context(|| format!("Failed to resolve {}", args.host))?
+        .next()
+        .with_context(|| format!("No address for {}", args.host))?;
+    eprintln!(
+        "Sweeping {} registers {}-{} of {} unit {}",
+        args.register_type.name(),
+        args.start,
+        args.end,
+        addr,
+        args.unit
+    );
+
+    let mut probe = Probe::new(
+        addr,
+        args.unit,
+        args.register_type,
+        Duration::from_millis(args.timeout_ms),
+    );
+    let sweep = probe.sweep(args.start, args.end, args.chunk).await?;
+    let ranges = sweep.ranges();
+    print_ranges(args.register_type, &ranges);
+    eprintln!("{}", sweep.summary(probe.requests));
+
+    if let Some(ref path) = args.output {
+        let selected = match args.select {
+            Some(ref list) => parse_selection(list, ranges.len())?,
+            None => (1..=ranges.len()).collect(),
+        };
+        let chosen: Vec<&Range> = selected.iter().map(|&i| &ranges[i - 1]).collect();
+        std::fs::write(path, starter_config(args, &chosen))
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        eprintln!(
+            "Wrote {} datapoints to {}",
+            chosen.iter().map(|r| r.words.len()).sum::<usize>(),
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Answer to one read request.
+pub enum Outcome {
+    Values(Vec<u16>),
+    Rejected(Exception),
+    NoResponse,
+}
+
+/// Client connection that is reopened after a timeout, since a late
+/// response would be taken for the answer to the next request.
+pub struct Probe {
+    addr: SocketAddr,
+    unit: u8,
+    table: Table,
+    timeout: Duration,
+    ctx: Option<Context>,
+    pub requests: u32,
+}
+
+impl Probe {
+    pub fn new(addr: SocketAddr, unit: u8, table: Table, timeout: Duration) -> Self {
+        Self {
+            addr,
+            unit,
+            table,
+            timeout,
+            ctx: None,
+            requests: 0,
+        }
+    }
+
+    /// Directs the following requests to another unit.
+    pub fn set_unit(&mut self, unit: u8) {
+        self.unit = unit;
+        if let Some(ref mut ctx) = self.ctx {
+            ctx.set_slave(Slave(unit));
+        }
+    }
+
+    /// Sends a request to the unit; `None` if no response came in time.
+    pub async fn call(
+        &mut self,
+        request: Request<'_>,
+    ) -> Result<Option<Result<Response, Exception>>> {
+        // A connection lost in between is retried once
+        for attempt in 0..2 {
+            if self.ctx.is_none() {
+                let connect = tcp::connect_slave(self.addr, Slave(self.unit));
+                let ctx = timeout(Duration::from_secs(5), connect)
+                    .await
+                    .context("Connection timeout")?
+                    .with_context(|| format!("Failed to connect to {}", self.addr))?;
+                self.ctx = Some(ctx);
+            }
+            let ctx = self.ctx.as_mut().context("Not connected")?;
+            self.requests += 1;
+            match timeout(self.timeout, ctx.call(request.clone())).await {
+                Ok(Ok(response)) => return Ok(Some(response)),
+                Ok(Err(tokio_modbus::Error::Transport(e))) if attempt == 0 => {
+                    eprintln!("Connection lost ({}), reconnecting", e);
+                    self.ctx = None;
+                }
+                Ok(Err(e)) => return Err(e).context("Request failed"),
+                Err(_) => {
+                    self.ctx = None;
+                    return Ok(None);
+                }
+            }
+        }
+        anyhow::bail!("Connection lost twice in a row")
+    }
+
+    pub async fn read(&mut self, address: u16, count: u16) -> Result<Outcome> {
+        let request = match self.table {
+            Table::Holding => Request::ReadHoldingRegisters(address, count),
+            Table::Input => Request::ReadInputRegisters(address, count),
+        };
+        Ok(match self.call(request).await? {
+            Some(Ok(
+                Response::ReadHoldingRegisters(words) | Response::ReadInputRegisters(words),
+            )) if words.len() == count as usize => Outcome::Values(words),
+            Some(Ok(response)) => anyhow::bail!("Unexpected response {:?}", response),
+            Some(Err(exception)) => Outcome::Rejected(exception),
+            None => Outcome::NoResponse,
+        })
+    }
+
+    /// Reads `start..=end` in chunks of `chunk` registers, bisecting chunks
+    /// rejected with an illegal data address.
+    async fn sweep(&mut self, start: u16, end: u16, chunk: u16) -> Result<Sweep> {
+        let mut sweep = Sweep::default();
+        let mut address = start as u32;
+        while address <= end as u32 {
+            let count = (chunk as u32).min(end as u32 + 1 - address);
+            // Halves are pushed second half first so addresses come in order
+            let mut pending = vec![(address as u16, count as u16)];
+            while let Some((address, count)) = pending.pop() {
+                match self.read(address, count).await? {
+                    Outcome::Values(words) => {
+                        // Counted in u32, as the last register may be 65535
+                        let addresses = (address as u32..).map(|a| a as u16);
+                        sweep.found.extend(addresses.zip(words));
+                    }
+                    Outcome::Rejected(Exception::IllegalFunction) => {
+                        anyhow::bail!(
+                            "The device does not support reading {} registers",
+                            self.table.name()
+                        )
+                    }
+                    Outcome::Rejected(
+                        exception @ (Exception::GatewayPathUnavailable
+                        | Exception::GatewayTargetDevice),
+                    ) => {
+                        anyhow::bail!("Unit {} is not reachable: {}", self.unit, exception)
+                    }
+                    Outcome::Rejected(Exception::IllegalDataAddress) if count > 1 => {
+                        let half = count / 2;
+                        pending.push((address + half, count - half));
+                        pending.push((address, half));
+                    }
+                    // Anything else fails the whole chunk; splitting a chunk
+                    // that times out would only multiply the timeouts
+                    Outcome::Rejected(exception) => {
+                        *sweep.rejected.entry(format!("{}", exception)).or_default() +=
+                            count as u32;
+                    }
+                    Outcome::NoResponse => sweep.unanswered += count as u32,
+                }
+            }
+            address += count;
+        }
+        Ok(sweep)
+    }
+}
+
+/// Result of a sweep.
+#[derive(Default)]
+struct Sweep {
+    /// Value of every register that was read
+    found: BTreeMap<u16, u16>,
+    /// Registers that could not be read, by exception
+    rejected: BTreeMap<String, u32>,
+    /// Registers whose request timed out
+    unanswered: u32,
+}
+
+/// Consecutive responding registers.
+struct Range {
+    start: u16,
+    words: Vec<u16>,
+}
+
+impl Range {
+    fn end(&self) -> u16 {
+        (self.start as u32 + self.words.len() as u32 - 1) as u16
+    }
+}
+
+impl Sweep {
+    fn ranges(&self) -> Vec<Range> {
+        let mut ranges: Vec<Range> = Vec::new();
+        for (&address, &word) in &self.found {
+            match ranges.last_mut() {
+                Some(range) if range.end() as u32 + 1 == address as u32 => range.words.push(word),
+                _ => ranges.push(Range {
+                    start: address,
+                    words: vec![word],
+                }),
+            }
+        }
+        ranges
+    }
+
+    fn summary(&self, requests: u32) -> String {
+        let mut summary = format!(
+            "{} registers in {} ranges, {} requests",
+            self.found.len(),
+            self.ranges().len(),
+            requests
+        );
+        for (exception, count) in &self.rejected {
+            let _ = write!(summary, "; {} rejected ({})", count, exception);
+        }
+        if self.unanswered > 0 {
+            let _ = write!(summary, "; {} without response", self.unanswered);
+        }
+        summary
+    }
+}
+
+/// Interpretations of the register at `index` of a range; the 32-bit ones
+/// take the next register too.
+fn candidates(words: &[u16], index: usize) -> [String; 7] {
+    let word = words[index];
+    let pair = words.get(index + 1).map(|&next| {
+        let abcd = ((word as u32) << 16) | next as u32;
+        let cdab = ((next as u32) << 16) | word as u32;
+        (abcd, cdab)
+    });
+    let dash = || "-".to_string();
+    [
+        word.to_string(),
+        (word as i16).to_string(),
+        pair.map_or_else(dash, |(abcd, _)| abcd.to_string()),
+        pair.map_or_else(dash, |(_, cdab)| cdab.to_string()),
+        pair.map_or_else(dash, |(abcd, _)| float(f32::from_bits(abcd))),
+        pair.map_or_else(dash, |(_, cdab)| float(f32::from_bits(cdab))),
+        word.to_be_bytes()
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect(),
+    ]
+}
+
+/// Floats far outside the range of real measurements are most likely not
+/// floats and left out.
+fn float(value: f32) -> String {
+    if !value.is_finite() || (value != 0.0 && !(1e-4..1e9).contains(&value.abs())) {
+        return "-".to_string();
+    }
+    let text = format!("{:.4}", value);
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+const COLUMNS: [&str; 9] = [
+    "ADDR", "HEX", "U16", "I16", "U32", "U32 CDAB", "F32", "F32 CDAB", "ASCII",
+];
+
+fn print_ranges(table: Table, ranges: &[Range]) {
+    for (number, range) in ranges.iter().enumerate() {
+        println!(
+            "#{} {} registers {}-{} ({})",
+            number + 1,
+            table.name(),
+            range.start,
+            range.end(),
+            range.words.len()
+        );
+        let rows: Vec<Vec<String>> = (0..range.words.len())
+            .map(|i| {
+                let mut row = vec![
+                    (range.start as usize + i).to_string(),
+                    format!("0x{:04X}", range.words[i]),
+                ];
+                row.extend(candidates(&range.words, i));
+                row
+            })
+            .collect();
+        let widths: Vec<usize> = COLUMNS
+            .iter()
+            .enumerate()
+            .map(|(c, title)| {
+                rows.iter()
+                    .map(|row| row[c].len())
+                    .chain([title.len()])
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        let line = |cells: Vec<&str>| {
+            let cells: Vec<String> = cells
+                .iter()
+                .zip(&widths)
+                .map(|(cell, &width)| format!("{:>width$}", cell))
+                .collect();
+            println!("  {}", cells.join("  "));
+        };
+        line(COLUMNS.to_vec());
+        for row in &rows {
+            line(row.iter().map(String::as_str).collect());
+        }
+        println!();
+    }
+}
+
+/// Parses range numbers such as `1,3-4` into a sorted list.
+fn parse_selection(list: &str, count: usize) -> Result<Vec<usize>> {
+    let mut selected = Vec::new();
+    for part in list.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (first, last) = part.split_once('-').unwrap_or((part, part));
+        let parse = |n: &str| -> Result<usize> {
+            let n: usize = n
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid range number '{}'", n))?;
+            if n == 0 || n > count {
+                anyhow::bail!("No range #{}, found {}", n, count);
+            }
+            Ok(n)
+        };
+        selected.extend(parse(first)?..=parse(last)?);
+    }
+    selected.sort_unstable();
+    selected.dedup();
+    Ok(selected)
+}
+
+/// Config with one u16 datapoint per register of the chosen ranges. The
+/// other interpretations are noted in comments, as a hint for merging
+/// registers into wider types.
+fn starter_config(args: &DiscoverArgs, ranges: &[&Range]) -> String {
+    let quote = |text: &str| serde_json::to_string(text).unwrap_or_default();
+    let table = args.register_type.name();
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "# Starter config from a discovery of {}:{} unit {}",
+        args.host, args.port, args.unit
+    );
+    let _ = writeln!(
+        out,
+        "# Every register is a u16; the comments list the values as u16, i16,\n\
+         # u32, u32 CDAB, f32, f32 CDAB and ASCII."
+    );
+    let _ = writeln!(out, "server:");
+    let _ = writeln!(out, "  protocol: modbus");
+    let _ = writeln!(out, "  host: {}", quote(&args.host));
+    let _ = writeln!(out, "  port: {}", args.port);
+    let _ = writeln!(out, "  unit_id: {}", args.unit);
+    let _ = writeln!(out);
+    let _ = writeln!(out, "datapoints:");
+    for range in ranges {
+        let _ = writeln!(
+            out,
+            "  # {} registers {}-{}",
+            table,
+            range.start,
+            range.end()
+        );
+        for (i, word) in range.words.iter().enumerate() {
+            let address = range.start as usize + i;
+            let _ = writeln!(
+                out,
+                "  - name: {}  # 0x{:04X}: {}",
+                quote(&format!("{} {}", table, address)),
+                word,
+                candidates(&range.words, i).join(" | ")
+            );
+            let _ = writeln!(out, "    address: {}", address);
+            let _ = writeln!(out, "    length: 1");
+            let _ = writeln!(out, "    register_type: {}", table);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::simulate::Simulator;
+    use serde_json::json;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn bisects_to_the_served_registers() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = Config::from_json(json!({
+            "server": { "protocol": "modbus", "host": "127.0.0.1" },
+            "datapoints": [
+                { "name": "first", "address": 10, "length": 2, "data_type": "f32",
+                  "simulate": { "generator": "constant", "value": 1.5 } },
+                { "name": "last", "address": 37, "length": 1,
+                  "simulate": { "generator": "constant", "value": 7 } },
+            ],
+        }))
+        .unwrap();
+        let simulator = Simulator::new(&config.servers[0], config.datapoints).unwrap();
+        tokio::spawn(simulator.serve(listener));
+
+        let mut probe = Probe::new(addr, 1, Table::Holding, Duration::from_secs(1));
+        let sweep = probe.sweep(0, 99, 16).await.unwrap();
+        let ranges = sweep.ranges();
+        assert_eq!(ranges.len(), 1);
+        assert_eq!((ranges[0].start, ranges[0].end()), (10, 37));
+        assert_eq!(candidates(&ranges[0].words, 0)[4], "1.5");
+        assert_eq!(ranges[0].words[27], 7);
+        assert_eq!(sweep.rejected.values().sum::<u32>(), 100 - 28);
+    }
+
+    #[tokio::test]
+    async fn sweeps_up_to_the_last_register() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = Config::from_json(json!({
+            "server": { "protocol": "modbus", "host": "127.0.0.1" },
+            "datapoints": [
+                { "name": "last", "address": 65533, "length": 3, "data_type": "string",
+                  "simulate": { "generator": "constant", "value": "ENDING" } },
+            ],
+        }))
+        .unwrap();
+        let simulator = Simulator::new(&config.servers[0], config.datapoints).unwrap();
+        tokio::spawn(simulator.serve(listener));
+
+        let mut probe = Probe::new(addr, 1, Table::Holding, Duration::from_secs(1));
+        let sweep = probe.sweep(65500, 65535, 125).await.unwrap();
+        let ranges = sweep.ranges();
+        assert_eq!(ranges.len(), 1);
+        assert_eq!((ranges[0].start, ranges[0].end()), (65533, 65535));
+        assert_eq!(candidates(&ranges[0].words, 2)[6], "NG");
+    }
+
+    #[tokio::test]
+    async fn does_not_split_unanswered_chunks() {
+        // Accepts connections but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                connections.push(stream);
+            }
+        });
+
+        let mut probe = Probe::new(addr, 1, Table::Holding, Duration::from_millis(100));
+        let sweep = probe.sweep(0, 99, 50).await.unwrap();
+        assert!(sweep.found.is_empty());
+        assert_eq!(sweep.unanswered, 100);
+        assert_eq!(probe.requests, 2);
+    }
+}
@@ -0,0 +1,364 @@
+//! Log of alarm, bit, connection and Modbus exception events.
+//!
+//! Events can be appended to a JSON Lines file and read back on start, so
+//! the log survives restarts. Acknowledgements are logged as events too and
+//! replayed on load. The file is rewritten with the events kept in memory
+//! once it holds twice as many, so it does not grow without bound.
+
+use crate::config::Severity;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Number of events kept in memory
+pub const MAX_EVENTS: usize = 1000;
+
+/// Events in the file at which it is compacted to the last `MAX_EVENTS`
+const MAX_FILE_EVENTS: usize = 2 * MAX_EVENTS;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    AlarmRaised,
+    AlarmCleared,
+    AlarmAcknowledged,
+    BitChanged,
+    ConnectionLost,
+    ConnectionRestored,
+    ModbusException,
+}
+
+impl EventKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            EventKind::AlarmRaised => "ALARM",
+            EventKind::AlarmCleared => "CLEARED",
+            EventKind::AlarmAcknowledged => "ACK",
+            EventKind::BitChanged => "BIT",
+            EventKind::ConnectionLost => "CONN LOST",
+            EventKind::ConnectionRestored => "CONN OK",
+            EventKind::ModbusException => "EXCEPTION",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Event {
+    pub timestamp: DateTime<Local>,
+    pub kind: EventKind,
+    pub server: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub datapoint: Option<String>,
+    /// Alarm the event belongs to, e.g. "high > 80"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alarm: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity: Option<Severity>,
+    pub message: String,
+    /// Raised alarms only; restored from the acknowledgement events on load
+    #[serde(skip)]
+    pub acknowledged: bool,
+}
+
+impl Event {
+    pub fn new(kind: EventKind, server: &str, datapoint: Option<&str>, message: String) -> Self {
+        Self {
+            timestamp: Local::now(),
+            kind,
+            server: server.to_string(),
+            datapoint: datapoint.map(str::to_string),
+            alarm: None,
+            severity: None,
+            message,
+            acknowledged: false,
+        }
+    }
+
+    pub fn needs_acknowledgement(&self) -> bool {
+        self.kind == EventKind::AlarmRaised && !self.acknowledged
+    }
+
+    fn same_alarm(&self, other: &Event) -> bool {
+        self.alarm.is_some()
+            && self.alarm == other.alarm
+            && self.datapoint == other.datapoint
+            && self.server == other.server
+    }
+}
+
+/// In-memory tail of the event log, newest last.
+pub struct EventLog {
+    events: VecDeque<Event>,
+    file: Option<File>,
+    path: Option<PathBuf>,
+    /// Events in the file
+    lines: usize,
+}
+
+impl EventLog {
+    /// Event log that is not persisted.
+    pub fn new() -> Self {
+        Self {
+            events: VecDeque::new(),
+            file: None,
+            path: None,
+            lines: 0,
+        }
+    }
+
+    /// Loads the events stored at `path` and appends new ones to it.
+    /// Lines that can't be parsed are skipped.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let mut log = Self::new();
+        if path.exists() {
+            let content = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read event log {}", path.display()))?;
+            for line in content.lines().filter(|line| !line.trim().is_empty()) {
+                log.lines += 1;
+                match serde_json::from_str::<Event>(line) {
+                    Ok(event) => log.push(event),
+                    Err(e) => warn!("Skipping event log line: {}", e),
+                }
+            }
+        }
+        log.path = Some(path.to_path_buf());
+        if log.lines >= MAX_FILE_EVENTS {
+            log.compact()?;
+        } else {
+            log.file = Some(append(path)?);
+        }
+        Ok(log)
+    }
+
+    pub fn events(&self) -> &VecDeque<Event> {
+        &self.events
+    }
+
+    /// Raised alarms that have not been acknowledged yet.
+    pub fn unacknowledged(&self) -> usize {
+        self.events
+            .iter()
+            .filter(|event| event.needs_acknowledgement())
+            .count()
+    }
+
+    pub fn record(&mut self, event: Event) {
+        if let Some(ref mut file) = self.file {
+            let written = serde_json::to_string(&event)
+                .map_err(anyhow::Error::from)
+                .and_then(|line| Ok(writeln!(file, "{}", line)?));
+            match written {
+                Ok(()) => self.lines += 1,
+                Err(e) => warn!("Failed to write event log: {}", e),
+            }
+        }
+        self.push(event);
+        if self.file.is_some()
+            && self.lines >= MAX_FILE_EVENTS
+            && let Err(e) = self.compact()
+        {
+            warn!("Failed to compact event log: {:#}", e);
+        }
+    }
+
+    /// Replaces the file with the events kept in memory.
+    fn compact(&mut self) -> Result<()> {
+        let Some(ref path) = self.path else {
+            return Ok(());
+        };
+        let mut content = String::new();
+        for event in &self.events {
+            content.push_str(&serde_json::to_string(event)?);
+            content.push('\n');
+        }
+        let mut temp = path.clone().into_os_string();
+        temp.push(".tmp");
+        fs::write(&temp, content)
+            .and_then(|_| fs::rename(&temp, path))
+            .with_context(|| format!("Failed to rewrite event log {}", path.display()))?;
+        self.file = Some(append(path)?);
+        self.lines = self.events.len();
+        Ok(())
+    }
+
+    /// Acknowledges the raised alarm at `index` along with any earlier
+    /// unacknowledged raises of the same alarm. Returns false if the event
+    /// is not an unacknowledged alarm.
+    pub fn acknowledge(&mut self, index: usize) -> bool {
+        let Some(event) = self.events.get(index) else {
+            return false;
+        };
+        if !event.needs_acknowledgement() {
+            return false;
+        }
+        let mut ack = Event::new(
+            EventKind::AlarmAcknowledged,
+            &event.server,
+            event.datapoint.as_deref(),
+            format!("Acknowledged: {}", event.message),
+        );
+        ack.alarm = event.alarm.clone();
+        ack.severity = event.severity;
+        self.record(ack);
+        true
+    }
+
+    fn push(&mut self, event: Event) {
+        if event.kind == EventKind::AlarmAcknowledged {
+            for raised in self.events.iter_mut().filter(|e| e.same_alarm(&event)) {
+                raised.acknowledged = true;
+            }
+        }
+        while self.events.len() >= MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+}
+
+fn append(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open event log {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "datapoint_tui_{}_{}.jsonl",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn raised(datapoint: &str, alarm: &str) -> Event {
+        let mut event = Event::new(
+            EventKind::AlarmRaised,
+            "plant",
+            Some(datapoint),
+            format!("{} {}", datapoint, alarm),
+        );
+        event.alarm = Some(alarm.to_string());
+        event.severity = Some(Severity::Warning);
+        event
+    }
+
+    #[test]
+    fn acknowledgements_survive_a_restart() {
+        let path = temp_path("acks");
+        let mut log = EventLog::open(&path).unwrap();
+        log.record(raised("temp", "high > 80"));
+        log.record(raised("level", "low < 5"));
+        log.record(Event::new(
+            EventKind::BitChanged,
+            "plant",
+            Some("status"),
+            "run set".to_string(),
+        ));
+        log.record(raised("temp", "high > 80"));
+        assert_eq!(log.unacknowledged(), 3);
+        // Acknowledging the latest raise covers the earlier one too
+        assert!(log.acknowledge(3));
+        assert!(!log.acknowledge(3));
+        assert!(!log.acknowledge(2));
+        drop(log);
+
+        // Written by an older version or cut off by a crash
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        writeln!(file, "{{\"timestamp\":").unwrap();
+        drop(file);
+
+        let log = EventLog::open(&path).unwrap();
+        let kinds: Vec<EventKind> = log.events().iter().map(|e| e.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                EventKind::AlarmRaised,
+                EventKind::AlarmRaised,
+                EventKind::BitChanged,
+                EventKind::AlarmRaised,
+                EventKind::AlarmAcknowledged,
+            ]
+        );
+        let acknowledged: Vec<bool> = log.events().iter().map(|e| e.acknowledged).collect();
+        assert_eq!(acknowledged, [true, false, false, true, false]);
+        assert_eq!(log.unacknowledged(), 1);
+        assert_eq!(log.events()[1].severity, Some(Severity::Warning));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn reload_keeps_the_latest_events() {
+        let path = temp_path("tail");
+        let mut log = EventLog::open(&path).unwrap();
+        for i in 0..MAX_EVENTS + 5 {
+            log.record(Event::new(
+                EventKind::ModbusException,
+                "plant",
+                None,
+                format!("event {}", i),
+            ));
+        }
+        assert_eq!(log.events().len(), MAX_EVENTS);
+        drop(log);
+
+        let mut log = EventLog::open(&path).unwrap();
+        assert_eq!(log.events().len(), MAX_EVENTS);
+        assert_eq!(log.events()[0].message, "event 5");
+        // New events are appended to the same file
+        log.record(raised("temp", "high > 80"));
+        drop(log);
+        let log = EventLog::open(&path).unwrap();
+        assert_eq!(log.events()[0].message, "event 6");
+        assert_eq!(log.unacknowledged(), 1);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn compacts_the_file() {
+        let path = temp_path("compact");
+        let event = |i: usize| {
+            Event::new(
+                EventKind::BitChanged,
+                "plant",
+                Some("status"),
+                format!("event {}", i),
+            )
+        };
+        let lines = |path: &Path| fs::read_to_string(path).unwrap().lines().count();
+
+        let mut log = EventLog::open(&path).unwrap();
+        for i in 0..MAX_FILE_EVENTS - 1 {
+            log.record(event(i));
+        }
+        assert_eq!(lines(&path), MAX_FILE_EVENTS - 1);
+        log.record(event(MAX_FILE_EVENTS - 1));
+        assert_eq!(lines(&path), MAX_EVENTS);
+        log.record(event(MAX_FILE_EVENTS));
+        assert_eq!(lines(&path), MAX_EVENTS + 1);
+        drop(log);
+
+        let log = EventLog::open(&path).unwrap();
+        assert_eq!(log.events().len(), MAX_EVENTS);
+        assert_eq!(log.events()[0].message, format!("event {}", MAX_EVENTS + 1));
+        assert_eq!(
+            log.events()[MAX_EVENTS - 1].message,
+            format!("event {}", MAX_FILE_EVENTS)
+        );
+        let _ = fs::remove_file(&path);
+    }
+}
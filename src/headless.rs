@@ -0,0 +1,152 @@
+//! Scanning without the terminal UI, e.g. as a service on an edge device.
+//!
+//! The scan tasks feed the same sinks as in the UI (recording file, JSON
+//! Lines on stdout). Events go to the event log and, like connection state
+//! changes, are logged through `log`.
+
+use crate::config::Config;
+use crate::events::{Event, EventKind, EventLog};
+use crate::scanner::{ConnectionState, Scanner};
+use anyhow::Result;
+use log::{Level, info, log, warn};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, timeout};
+
+/// How long shutdown waits for a scan in progress
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Collects events and connection changes until SIGINT or SIGTERM.
+pub async fn run(
+    config: &Config,
+    scanners: Vec<Arc<Mutex<Scanner>>>,
+    tasks: Vec<JoinHandle<()>>,
+    mut events: EventLog,
+) -> Result<()> {
+    info!(
+        "Scanning {} servers every {}ms",
+        scanners.len(),
+        config.scan_interval_ms
+    );
+    let mut states = vec![ConnectionState::Idle; scanners.len()];
+    let mut update_interval = interval(Duration::from_millis(100));
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            _ = update_interval.tick() => {
+                for (i, scanner) in scanners.iter().enumerate() {
+                    if let Ok(mut scanner) = scanner.try_lock() {
+                        log_events(&mut events, scanner.take_events());
+                        let state = scanner.connection_state();
+                        if !same_state(&states[i], &state) {
+                            log_connection(&config.servers[i].name, &state);
+                        }
+                        states[i] = state;
+                    }
+                }
+            }
+            signal = &mut shutdown => {
+                info!("Received {}, shutting down", signal?);
+                break;
+            }
+        }
+    }
+
+    // Let scans in progress finish so their results are recorded. The locks
+    // are held while the scan tasks are stopped, so none starts a new scan.
+    let mut held = Vec::new();
+    for (i, scanner) in scanners.iter().enumerate() {
+        match timeout(SHUTDOWN_TIMEOUT, scanner.lock()).await {
+            Ok(scanner) => held.push(scanner),
+            Err(_) => warn!(
+                "Scan of {} still running, not waiting for it",
+                config.servers[i].name
+            ),
+        }
+    }
+    for task in &tasks {
+        task.abort();
+    }
+    for task in tasks {
+        let _ = task.await;
+    }
+    drop(held);
+
+    // With the tasks gone, collect the events of the last scans
+    for scanner in &scanners {
+        log_events(&mut events, scanner.lock().await.take_events());
+    }
+    Ok(())
+}
+
+fn log_events(log: &mut EventLog, events: Vec<Event>) {
+    for event in events {
+        let level = match event.kind {
+            EventKind::AlarmRaised | EventKind::ConnectionLost | EventKind::ModbusException => {
+                Level::Warn
+            }
+            _ => Level::Info,
+        };
+        match event.datapoint {
+            Some(ref datapoint) => log!(
+                level,
+                "{} {}/{}: {}",
+                event.kind.label(),
+                event.server,
+                datapoint,
+                event.message
+            ),
+            None => log!(
+                level,
+                "{} {}: {}",
+                event.kind.label(),
+                event.server,
+                event.message
+            ),
+        }
+        log.record(event);
+    }
+}
+
+/// Backoff states only differ in the retry countdown.
+fn same_state(a: &ConnectionState, b: &ConnectionState) -> bool {
+    std::mem::discriminant(a) == std::mem::discriminant(b)
+}
+
+fn log_connection(server: &str, state: &ConnectionState) {
+    match *state {
+        ConnectionState::Idle => {}
+        ConnectionState::Connecting => info!("{}: connecting", server),
+        ConnectionState::Connected => info!("{}: connected", server),
+        ConnectionState::Backoff { until, attempt } => warn!(
+            "{}: disconnected, retry #{} in {:.1}s",
+            server,
+            attempt,
+            until
+                .saturating_duration_since(tokio::time::Instant::now())
+                .as_secs_f32()
+        ),
+    }
+}
+
+/// Resolves with the name of the signal that asked us to stop.
+pub async fn shutdown_signal() -> Result<&'static str> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result.map(|_| "SIGINT").map_err(Into::into),
+            _ = terminate.recv() => Ok("SIGTERM"),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await?;
+        Ok("SIGINT")
+    }
+}
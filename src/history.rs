@@ -0,0 +1,171 @@
+use chrono::{DateTime, Local};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub timestamp: DateTime<Local>,
+    pub value: f64,
+}
+
+/// Bounded buffer of the most recent samples of a datapoint.
+///
+/// The samples are shared behind an `Arc` so that the snapshots the UI takes
+/// of the scanner's datapoints every tick don't copy the whole buffer.
+#[derive(Debug, Clone)]
+pub struct History {
+    samples: Arc<VecDeque<Sample>>,
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            samples: Arc::new(VecDeque::new()),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, timestamp: DateTime<Local>, value: f64) {
+        if self.capacity == 0 {
+            return;
+        }
+        let samples = Arc::make_mut(&mut self.samples);
+        while samples.len() >= self.capacity {
+            samples.pop_front();
+        }
+        samples.push_back(Sample { timestamp, value });
+    }
+
+    /// Samples newer than `window` before `now`, oldest first.
+    pub fn window(&self, now: DateTime<Local>, window: Duration) -> impl Iterator<Item = &Sample> {
+        let start = now - window;
+        self.samples.iter().filter(move |s| s.timestamp >= start)
+    }
+
+    /// The last `count` samples, oldest first.
+    pub fn latest(&self, count: usize) -> impl Iterator<Item = &Sample> {
+        self.samples
+            .iter()
+            .skip(self.samples.len().saturating_sub(count))
+    }
+
+    /// Value in effect at each of `buckets` equal slices of the window
+    /// ending at `now`: the last sample at or before the end of the slice,
+    /// or `None` before the first sample.
+    pub fn resample(
+        &self,
+        now: DateTime<Local>,
+        window: Duration,
+        buckets: usize,
+    ) -> Vec<Option<f64>> {
+        let start = now - window;
+        let step = window / buckets.max(1) as u32;
+        let mut samples = self.samples.iter().peekable();
+        let mut current = None;
+
+        (1..=buckets)
+            .map(|i| {
+                let end = start + step * i as u32;
+                while let Some(sample) = samples.next_if(|s| s.timestamp <= end) {
+                    current = Some(sample.value);
+                }
+                current
+            })
+            .collect()
+    }
+}
+
+const SPARK_LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Renders values as a string of block characters scaled between their
+/// minimum and maximum.
+pub fn sparkline(values: &[f64]) -> String {
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let range = max - min;
+
+    values
+        .iter()
+        .map(|v| {
+            if range > 0.0 {
+                let level = ((v - min) / range * (SPARK_LEVELS.len() - 1) as f64).round();
+                SPARK_LEVELS[level as usize]
+            } else {
+                SPARK_LEVELS[SPARK_LEVELS.len() / 2]
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(seconds: i64) -> DateTime<Local> {
+        Local.timestamp_opt(1_700_000_000 + seconds, 0).unwrap()
+    }
+
+    fn values<'a>(samples: impl Iterator<Item = &'a Sample>) -> Vec<f64> {
+        samples.map(|s| s.value).collect()
+    }
+
+    #[test]
+    fn keeps_the_latest_samples() {
+        let mut history = History::new(3);
+        for i in 0..5 {
+            history.push(at(i), i as f64);
+        }
+        assert_eq!(values(history.latest(10)), [2.0, 3.0, 4.0]);
+        assert_eq!(values(history.latest(2)), [3.0, 4.0]);
+
+        // Snapshots are not changed by later samples
+        let snapshot = history.clone();
+        history.push(at(5), 5.0);
+        assert_eq!(values(snapshot.latest(10)), [2.0, 3.0, 4.0]);
+        assert_eq!(values(history.latest(10)), [3.0, 4.0, 5.0]);
+
+        let mut disabled = History::new(0);
+        disabled.push(at(0), 1.0);
+        assert_eq!(disabled.latest(10).count(), 0);
+    }
+
+    #[test]
+    fn window_starts_at_now_minus_duration() {
+        let mut history = History::new(10);
+        for i in 0..5 {
+            history.push(at(i), i as f64);
+        }
+        let window = history.window(at(4), Duration::from_secs(2));
+        assert_eq!(values(window), [2.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn resample_holds_the_last_value() {
+        let mut history = History::new(10);
+        history.push(at(3), 1.0);
+        history.push(at(4), 2.0);
+        history.push(at(7), 3.0);
+        // Buckets end at 2, 4, 6, 8 and 10 seconds
+        let buckets = history.resample(at(10), Duration::from_secs(10), 5);
+        assert_eq!(buckets, [None, Some(2.0), Some(2.0), Some(3.0), Some(3.0)]);
+
+        // Samples before the window still set the first value
+        let buckets = history.resample(at(14), Duration::from_secs(4), 2);
+        assert_eq!(buckets, [Some(3.0), Some(3.0)]);
+        assert!(
+            History::new(10)
+                .resample(at(0), Duration::from_secs(1), 0)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn sparkline_scales_between_min_and_max() {
+        assert_eq!(sparkline(&[0.0, 7.0, 3.5, 1.0]), "▁█▅▂");
+        assert_eq!(sparkline(&[5.0, 5.0]), "▅▅");
+        assert_eq!(sparkline(&[]), "");
+    }
+}
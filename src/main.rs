@@ -1,40 +1,171 @@
+mod alarm;
+mod codec;
 mod config;
 mod datapoint;
+mod discover;
+mod events;
+mod headless;
+mod history;
+mod metrics;
+mod mqtt;
+mod protocol;
+mod read;
+mod read_plan;
+mod recorder;
+mod replay;
 mod scanner;
+mod simulate;
 mod ui;
+mod units;
 
 use anyhow::Result;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use config::Config;
 use crossterm::{
     event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
     execute,
     terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
 };
+use events::EventLog;
+use metrics::Metrics;
+use mqtt::Mqtt;
 use ratatui::{Terminal, backend::CrosstermBackend};
-use scanner::Scanner;
+use recorder::{RecordFormat, RecordOptions, Recorder};
+use replay::Replay;
+use scanner::{ConnectionState, Scanner};
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::{io, time::Duration};
-use tokio::{sync::Mutex, time::interval};
+use tokio::{sync::Mutex, task::JoinHandle, time::interval};
 use ui::App;
 
 #[derive(Parser, Debug)]
 #[command(name = "datapoint_tui")]
 #[command(about = "A TUI dashboard for monitoring server datapoints", long_about = None)]
 struct Args {
-    #[arg(short, long, default_value = "config.yaml")]
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(short, long, global = true, default_value = "config.yaml")]
     config: String,
 
-    #[arg(short, long, help = "Enable debug logging to file")]
+    #[arg(short, long, global = true, help = "Enable debug logging to file")]
     debug: bool,
+
+    #[arg(
+        long,
+        help = "Run without the terminal UI, printing every scan as JSON Lines on stdout"
+    )]
+    headless: bool,
+
+    #[arg(
+        long,
+        requires = "headless",
+        help = "Don't print scans on stdout in headless mode"
+    )]
+    quiet: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Record every scan to FILE (toggle at runtime with R)"
+    )]
+    record: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Recording format [default: from the file extension, else csv]"
+    )]
+    record_format: Option<RecordFormat>,
+
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser = recorder::parse_size,
+        help = "Start a new recording file at this size, e.g. 10M"
+    )]
+    rotate_size: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = recorder::parse_duration,
+        help = "Start a new recording file after this time, e.g. 1h"
+    )]
+    rotate_every: Option<Duration>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["record", "headless", "metrics_listen"],
+        help = "Play back a recording made with --record instead of polling the servers"
+    )]
+    replay: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "ADDR",
+        help = "Serve Prometheus metrics on ADDR, e.g. 127.0.0.1:9100"
+    )]
+    metrics_listen: Option<SocketAddr>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Read the datapoints once, print them and exit (1: read failed, 2: alarm active)
+    Read(read::ReadArgs),
+    /// Serve the configured datapoints as a Modbus TCP server
+    Simulate(simulate::SimulateArgs),
+    /// Find the registers a device responds to and print their values
+    Discover(discover::DiscoverArgs),
+    /// Find the unit IDs that respond behind a Modbus TCP gateway
+    Units(units::UnitsArgs),
+}
+
+/// Where the datapoints shown in the UI come from.
+enum Source {
+    Live {
+        scanners: Vec<Arc<Mutex<Scanner>>>,
+        recorder: Recorder,
+        mqtt: Option<Mqtt>,
+    },
+    Replay(Box<Replay>),
+}
+
+impl Args {
+    fn record_options(&self) -> RecordOptions {
+        let format = self.record_format.unwrap_or_else(|| {
+            match self.record.as_ref().and_then(|p| p.extension()) {
+                Some(ext) if ext == "jsonl" || ext == "json" => RecordFormat::Jsonl,
+                _ => RecordFormat::Csv,
+            }
+        });
+        let path = self.record.clone().unwrap_or_else(|| {
+            PathBuf::from(format!("datapoint_tui_record.{}", format.extension()))
+        });
+        RecordOptions {
+            path,
+            format,
+            max_size: self.rotate_size,
+            max_age: self.rotate_every,
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
 
-    // Initialize logger if debug flag is set
-    if args.debug {
+    // Headless mode and the simulator log to stderr, the UI only logs to a
+    // file when the debug flag is set
+    if args.headless || matches!(args.command, Some(Command::Simulate(_))) {
+        let level = if args.debug { "debug" } else { "info" };
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(level))
+            .target(env_logger::Target::Stderr)
+            .init();
+    } else if args.debug {
         env_logger::Builder::from_default_env()
             .filter_level(log::LevelFilter::Debug)
             .target(env_logger::Target::Pipe(Box::new(std::fs::File::create(
@@ -44,41 +175,90 @@ async fn main() -> Result<()> {
         log::info!("Debug logging enabled");
     }
 
+    if let Some(Command::Read(ref read_args)) = args.command {
+        let code = read::run(&args.config, read_args).await?;
+        std::process::exit(code);
+    }
+    if let Some(Command::Simulate(ref simulate_args)) = args.command {
+        return simulate::run(&args.config, simulate_args).await;
+    }
+    if let Some(Command::Discover(ref discover_args)) = args.command {
+        return discover::run(discover_args).await;
+    }
+    if let Some(Command::Units(ref units_args)) = args.command {
+        return units::run(units_args).await;
+    }
+
     let config = Config::load(&args.config)?;
 
-    let server_info = format!(
-        "{}://{}:{}",
-        config.server.protocol, config.server.host, config.server.port
-    );
-    let scan_interval = config.scan_interval_ms;
+    if let Some(ref path) = args.replay {
+        let replay = Replay::load(&config, path)?;
+        // Replayed events are not added to the live event log
+        let mut app = App::new(config.scan_interval_ms, EventLog::new());
+        for (i, server) in config.servers.iter().enumerate() {
+            app.add_server(
+                server.name.clone(),
+                server.describe(),
+                replay.datapoints(i).to_vec(),
+            );
+        }
+        app.replay = Some(replay.status());
+        return run_tui(app, Source::Replay(Box::new(replay))).await;
+    }
 
-    let scanner = Arc::new(Mutex::new(Scanner::new(config)));
+    let events = match config.event_log {
+        Some(ref path) => EventLog::open(path)?,
+        None => EventLog::new(),
+    };
 
-    let scanner_clone = scanner.clone();
-    tokio::spawn(async move {
-        // Wait a bit before first scan to let UI initialize
-        tokio::time::sleep(Duration::from_millis(100)).await;
+    let columns = config.column_names();
+    let recorder = Recorder::start(
+        args.record_options(),
+        columns.clone(),
+        args.record.is_some(),
+    );
+    let mut sinks = vec![recorder.clone()];
+    if args.headless && !args.quiet {
+        sinks.push(Recorder::start(RecordOptions::stdout(), columns, true));
+    }
+    let metrics = match args.metrics_listen {
+        Some(addr) => Some(Metrics::serve(addr, config.servers.len()).await?),
+        None => None,
+    };
+    let mqtt = config.mqtt.clone().map(Mqtt::start);
+    let (scanners, tasks) = start_scanners(&config, sinks, metrics, mqtt.clone());
 
-        loop {
-            {
-                let mut scanner = scanner_clone.lock().await;
-                // Run one scan, then release the lock
-                let _ = scanner.scan_once().await;
-            }
-            // Wait for scan interval before next scan
-            tokio::time::sleep(Duration::from_millis(scan_interval)).await;
-        }
-    });
+    if args.headless {
+        return headless::run(&config, scanners, tasks, events).await;
+    }
 
+    let mut app = App::new(config.scan_interval_ms, events);
+    for (server, scanner) in config.servers.iter().zip(&scanners) {
+        app.add_server(
+            server.name.clone(),
+            server.describe(),
+            scanner.lock().await.get_datapoints().to_vec(),
+        );
+    }
+    run_tui(
+        app,
+        Source::Live {
+            scanners,
+            recorder,
+            mqtt,
+        },
+    )
+    .await
+}
+
+async fn run_tui(mut app: App, source: Source) -> Result<()> {
     enable_raw_mode()?;
     let mut stdout = io::stdout();
     execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    let mut app = App::new(server_info, scan_interval);
-
-    let res = run_app(&mut terminal, &mut app, scanner).await;
+    let res = run_app(&mut terminal, &mut app, source).await;
 
     disable_raw_mode()?;
     execute!(
@@ -95,10 +275,68 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// Spawns one scan task per server. Every scan is handed to all `sinks`,
+/// the metrics exporter and the MQTT publisher. Returns the scanners and
+/// their tasks.
+fn start_scanners(
+    config: &Config,
+    sinks: Vec<Recorder>,
+    metrics: Option<Metrics>,
+    mqtt: Option<Mqtt>,
+) -> (Vec<Arc<Mutex<Scanner>>>, Vec<JoinHandle<()>>) {
+    let scan_interval = config.scan_interval_ms;
+
+    // One scanner per server, each polled by its own task so a slow or
+    // unreachable server does not hold up the others
+    let mut scanners = Vec::new();
+    let mut tasks = Vec::new();
+    let mut first_column = 0;
+    for (index, server) in config.servers.iter().enumerate() {
+        let scanner = Scanner::new(
+            server.clone(),
+            config.datapoints_for(&server.name),
+            config.history_size,
+        );
+        let server_column = first_column;
+        first_column += scanner.get_datapoints().len();
+        let scanner = Arc::new(Mutex::new(scanner));
+        scanners.push(scanner.clone());
+        let sinks = sinks.clone();
+        let metrics = metrics.clone();
+        let mqtt = mqtt.clone();
+        let server_name = server.name.clone();
+
+        tasks.push(tokio::spawn(async move {
+            // Wait a bit before first scan to let UI initialize
+            tokio::time::sleep(Duration::from_millis(100)).await;
+
+            loop {
+                {
+                    let mut scanner = scanner.lock().await;
+                    // Run one scan, then release the lock
+                    let _ = scanner.scan_once().await;
+                    for sink in &sinks {
+                        sink.record(&server_name, server_column, scanner.get_datapoints());
+                    }
+                    if let Some(ref metrics) = metrics {
+                        metrics.update(index, &scanner);
+                    }
+                    if let Some(ref mqtt) = mqtt {
+                        mqtt.publish(&scanner);
+                    }
+                }
+                // Wait for scan interval before next scan
+                tokio::time::sleep(Duration::from_millis(scan_interval)).await;
+            }
+        }));
+    }
+    (scanners, tasks)
+}
+
 async fn run_app<B: ratatui::backend::Backend>(
     terminal: &mut Terminal<B>,
     app: &mut App,
-    scanner: Arc<Mutex<Scanner>>,
+    mut source: Source,
 ) -> Result<()> {
     let mut update_interval = interval(Duration::from_millis(100));
 
@@ -112,6 +350,103 @@ async fn run_app<B: ratatui::backend::Backend>(
         if event::poll(Duration::from_millis(0))?
             && let Event::Key(key) = event::read()?
         {
+            if let Some(ref mut seek) = app.seek {
+                match key.code {
+                    KeyCode::Esc => app.seek = None,
+                    KeyCode::Enter => {
+                        if let Source::Replay(ref mut replay) = source {
+                            match replay.parse_time(&seek.input) {
+                                Ok(time) => {
+                                    replay.seek(time);
+                                    app.seek = None;
+                                }
+                                Err(e) => seek.error = Some(e.to_string()),
+                            }
+                        }
+                    }
+                    KeyCode::Backspace => {
+                        seek.input.pop();
+                    }
+                    KeyCode::Char(c) => seek.input.push(c),
+                    _ => {}
+                }
+                continue;
+            }
+
+            if app.edit.is_some() {
+                match key.code {
+                    KeyCode::Esc => app.cancel_edit(),
+                    KeyCode::Enter => {
+                        if let Some((index, value)) = app.submit_edit()
+                            && let Some((server, index)) = app.locate(index)
+                            && let Source::Live { ref scanners, .. } = source
+                        {
+                            let scanner = scanners[server].clone();
+                            tokio::spawn(async move {
+                                let mut scanner = scanner.lock().await;
+                                let _ = scanner.write_datapoint(index, value).await;
+                            });
+                        }
+                    }
+                    KeyCode::Down => app.move_edit_option(1),
+                    KeyCode::Up => app.move_edit_option(-1),
+                    KeyCode::Backspace => {
+                        if let Some(edit) = app.edit.as_mut() {
+                            edit.input.pop();
+                        }
+                    }
+                    KeyCode::Char(c) => {
+                        if let Some(edit) = app.edit.as_mut() {
+                            edit.input.push(c);
+                        }
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+
+            if app.pending_bit.is_some() {
+                match key.code {
+                    KeyCode::Char('y') | KeyCode::Enter => {
+                        if let Some(toggle) = app.confirm_bit_toggle()
+                            && let Some((server, index)) = app.locate(toggle.index)
+                            && let Source::Live { ref scanners, .. } = source
+                        {
+                            let scanner = scanners[server].clone();
+                            tokio::spawn(async move {
+                                let mut scanner = scanner.lock().await;
+                                let _ = scanner.write_bit(index, toggle.bit, toggle.value).await;
+                            });
+                        }
+                    }
+                    KeyCode::Char('n') | KeyCode::Esc => app.cancel_bit_toggle(),
+                    _ => {}
+                }
+                continue;
+            }
+
+            if app.log_cursor.is_some() {
+                match key.code {
+                    KeyCode::Char('l') | KeyCode::Esc => app.toggle_log(),
+                    KeyCode::Down => app.next_event(),
+                    KeyCode::Up => app.previous_event(),
+                    KeyCode::Char('a') => app.acknowledge_event(),
+                    _ => {}
+                }
+                continue;
+            }
+
+            if app.bit_cursor.is_some() {
+                match key.code {
+                    KeyCode::Char('b') | KeyCode::Esc => app.unfocus_bits(),
+                    KeyCode::Down => app.next_bit(),
+                    KeyCode::Up => app.previous_bit(),
+                    KeyCode::Char(' ') => app.request_bit_toggle(),
+                    _ => {}
+                }
+                continue;
+            }
+
             match key.code {
                 KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
                 KeyCode::Char('c') if key.modifiers.contains(event::KeyModifiers::CONTROL) => {
@@ -119,23 +454,68 @@ async fn run_app<B: ratatui::backend::Backend>(
                 }
                 KeyCode::Down => app.next(),
                 KeyCode::Up => app.previous(),
-                _ => {}
+                KeyCode::Char('w') => app.open_edit(),
+                KeyCode::Char('b') => app.focus_bits(),
+                KeyCode::Tab => app.next_server_filter(),
+                KeyCode::Char('g') => app.toggle_chart(),
+                KeyCode::Char('t') => app.next_chart_window(),
+                KeyCode::Char('r') => app.toggle_raw(),
+                KeyCode::Char('l') => app.toggle_log(),
+                code => match source {
+                    Source::Live { ref recorder, .. } => {
+                        if code == KeyCode::Char('R') {
+                            recorder.toggle();
+                        }
+                    }
+                    Source::Replay(ref mut replay) => match code {
+                        KeyCode::Char(' ') => replay.toggle_pause(),
+                        KeyCode::Char('s') => replay.next_speed(),
+                        KeyCode::Right => replay.skip(true),
+                        KeyCode::Left => replay.skip(false),
+                        KeyCode::Char('/') => app.open_seek(),
+                        _ => {}
+                    },
+                },
             }
         }
 
         tokio::select! {
             _ = update_interval.tick() => {
-                // Try to get datapoints with timeout
-                let datapoints_result = tokio::time::timeout(
-                    Duration::from_millis(50),
-                    async {
-                        let scanner_guard = scanner.lock().await;
-                        scanner_guard.get_datapoints().to_vec()
-                    }
-                ).await;
+                match source {
+                    Source::Live { ref scanners, ref recorder, ref mqtt } => {
+                        // Take a snapshot of every scanner that is not busy; a
+                        // scanner in the middle of a scan keeps its last snapshot
+                        for (i, scanner) in scanners.iter().enumerate() {
+                            if let Ok(mut scanner) = scanner.try_lock() {
+                                app.log_events(scanner.take_events());
+                                app.update_server(
+                                    i,
+                                    scanner.get_datapoints().to_vec(),
+                                    scanner.connection_state(),
+                                );
+                            }
+                        }
 
-                if let Ok(datapoints) = datapoints_result {
-                    app.update_datapoints(datapoints);
+                        app.recording = recorder
+                            .is_enabled()
+                            .then(|| recorder.path().display().to_string());
+                        app.mqtt = mqtt.as_ref().map(Mqtt::status);
+                    }
+                    Source::Replay(ref mut replay) => {
+                        replay.tick();
+                        if replay.take_reset() {
+                            app.clear_events();
+                        }
+                        for i in 0..app.servers.len() {
+                            app.log_events(replay.take_events(i));
+                            app.update_server(
+                                i,
+                                replay.datapoints(i).to_vec(),
+                                ConnectionState::Connected,
+                            );
+                        }
+                        app.replay = Some(replay.status());
+                    }
                 }
 
                 // Always redraw UI even if we couldn't get data
@@ -0,0 +1,370 @@
+//! Prometheus exporter serving `/metrics` in the text exposition format.
+//!
+//! The scan loops store the samples of a server after every scan, so a
+//! scrape never waits for a scan in progress. The HTTP side is a minimal
+//! HTTP/1.1 responder; one request per connection is all Prometheus needs.
+
+use crate::datapoint::DataValue;
+use crate::scanner::{ConnectionState, Scanner};
+use anyhow::{Context, Result};
+use log::{debug, info};
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Largest request accepted, headers included
+const MAX_REQUEST: usize = 8192;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Metric families in the order they are exposed: name, type and help text.
+const FAMILIES: [(&str, &str, &str); 9] = [
+    (
+        "datapoint_tui_value",
+        "gauge",
+        "Datapoint value in engineering units",
+    ),
+    (
+        "datapoint_tui_bit",
+        "gauge",
+        "Value of a named bitfield flag or multi-bit field",
+    ),
+    (
+        "datapoint_tui_datapoint_error",
+        "gauge",
+        "1 if the last read of the datapoint failed",
+    ),
+    (
+        "datapoint_tui_connected",
+        "gauge",
+        "1 while the connection to the server is up",
+    ),
+    (
+        "datapoint_tui_scan_duration_seconds",
+        "gauge",
+        "Duration of the last scan of the server",
+    ),
+    (
+        "datapoint_tui_scans_total",
+        "counter",
+        "Scans of the server",
+    ),
+    (
+        "datapoint_tui_read_errors_total",
+        "counter",
+        "Failed read requests by kind",
+    ),
+    (
+        "datapoint_tui_reconnects_total",
+        "counter",
+        "Connections made after a lost or failed one",
+    ),
+    (
+        "datapoint_tui_active_alarms",
+        "gauge",
+        "Active alarms of the server's datapoints",
+    ),
+];
+
+/// One sample: metric family, rendered label set and value.
+struct Sample {
+    family: &'static str,
+    labels: String,
+    value: f64,
+}
+
+/// Handle shared by the scan loops and the HTTP listener.
+#[derive(Clone)]
+pub struct Metrics {
+    /// Samples of every server from its last scan
+    servers: Arc<Mutex<Vec<Vec<Sample>>>>,
+}
+
+impl Metrics {
+    /// Binds `addr` and serves `/metrics` for `server_count` servers in the
+    /// background.
+    pub async fn serve(addr: SocketAddr, server_count: usize) -> Result<Self> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("Failed to listen for metrics on {}", addr))?;
+        info!("Serving metrics on http://{}/metrics", addr);
+        Ok(Self::start(listener, server_count))
+    }
+
+    fn start(listener: TcpListener, server_count: usize) -> Self {
+        let metrics = Self {
+            servers: Arc::new(Mutex::new((0..server_count).map(|_| Vec::new()).collect())),
+        };
+        let handler = metrics.clone();
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, peer)) => {
+                        let metrics = handler.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = metrics.respond(stream).await {
+                                debug!("Metrics request from {} failed: {}", peer, e);
+                            }
+                        });
+                    }
+                    Err(e) => debug!("Failed to accept metrics connection: {}", e),
+                }
+            }
+        });
+        metrics
+    }
+
+    /// Replaces the samples of server `index` with the scanner's state.
+    pub fn update(&self, index: usize, scanner: &Scanner) {
+        let samples = server_samples(scanner);
+        if let Ok(mut servers) = self.servers.lock()
+            && let Some(server) = servers.get_mut(index)
+        {
+            *server = samples;
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        let Ok(servers) = self.servers.lock() else {
+            return out;
+        };
+        for (family, kind, help) in FAMILIES {
+            let _ = writeln!(out, "# HELP {} {}", family, help);
+            let _ = writeln!(out, "# TYPE {} {}", family, kind);
+            for sample in servers.iter().flatten().filter(|s| s.family == family) {
+                let _ = writeln!(
+                    out,
+                    "{}{{{}}} {}",
+                    sample.family,
+                    sample.labels,
+                    number(sample.value)
+                );
+            }
+        }
+        out
+    }
+
+    async fn respond(&self, mut stream: TcpStream) -> Result<()> {
+        let mut request = Vec::new();
+        let mut buffer = [0u8; 1024];
+        tokio::time::timeout(REQUEST_TIMEOUT, async {
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                let read = stream.read(&mut buffer).await?;
+                if read == 0 || request.len() + read > MAX_REQUEST {
+                    anyhow::bail!("incomplete request");
+                }
+                request.extend_from_slice(&buffer[..read]);
+            }
+            Ok(())
+        })
+        .await
+        .context("request timeout")??;
+
+        let request = String::from_utf8_lossy(&request);
+        let mut parts = request.split_whitespace();
+        let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+        let (status, body) = match (method, path.split('?').next()) {
+            ("GET", Some("/metrics")) => ("200 OK", self.render()),
+            ("GET", Some("/")) => ("200 OK", "datapoint_tui metrics: /metrics\n".to_string()),
+            ("GET", _) => ("404 Not Found", "Not found\n".to_string()),
+            _ => ("405 Method Not Allowed", "Method not allowed\n".to_string()),
+        };
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await?;
+        Ok(())
+    }
+}
+
+fn server_samples(scanner: &Scanner) -> Vec<Sample> {
+    let server = &scanner.server().name;
+    let server_label = format!("server=\"{}\"", escape(server));
+    let mut samples = Vec::new();
+    let mut push = |family, labels: String, value: f64| {
+        samples.push(Sample {
+            family,
+            labels,
+            value,
+        })
+    };
+
+    let mut active_alarms = 0;
+    for dp in scanner.get_datapoints() {
+        let labels = format!(
+            "{},unit=\"{}\",datapoint=\"{}\"",
+            server_label,
+            dp.unit_id,
+            escape(&dp.name)
+        );
+        push(
+            "datapoint_tui_datapoint_error",
+            labels.clone(),
+            dp.error.is_some() as u8 as f64,
+        );
+        // Stale values are left out rather than exported as current
+        if dp.error.is_some() {
+            continue;
+        }
+        // Bitfields and coils have no value in engineering units; their
+        // named bits are exported below
+        let numeric = dp.value.as_ref().is_some_and(DataValue::is_numeric);
+        if numeric && let Some(value) = dp.engineering_value() {
+            let uom = escape(dp.unit.as_deref().unwrap_or(""));
+            push(
+                "datapoint_tui_value",
+                format!("{},uom=\"{}\"", labels, uom),
+                value,
+            );
+        }
+        for field in dp.get_bitfield_status().into_iter().flatten() {
+            push(
+                "datapoint_tui_bit",
+                format!(
+                    "{},bit=\"{}\",name=\"{}\"",
+                    labels,
+                    field.bit,
+                    escape(&field.name)
+                ),
+                field.value as f64,
+            );
+        }
+        active_alarms += dp.alarms.iter().filter(|a| a.is_active()).count();
+    }
+
+    let stats = scanner.stats();
+    let connected = scanner.connection_state() == ConnectionState::Connected;
+    push(
+        "datapoint_tui_connected",
+        server_label.clone(),
+        connected as u8 as f64,
+    );
+    push(
+        "datapoint_tui_scan_duration_seconds",
+        server_label.clone(),
+        stats.last_scan.as_secs_f64(),
+    );
+    push(
+        "datapoint_tui_scans_total",
+        server_label.clone(),
+        stats.scans as f64,
+    );
+    for (kind, count) in &stats.read_errors {
+        push(
+            "datapoint_tui_read_errors_total",
+            format!("{},kind=\"{}\"", server_label, kind),
+            *count as f64,
+        );
+    }
+    push(
+        "datapoint_tui_reconnects_total",
+        server_label.clone(),
+        stats.reconnects as f64,
+    );
+    push(
+        "datapoint_tui_active_alarms",
+        server_label,
+        active_alarms as f64,
+    );
+    samples
+}
+
+/// Formats a sample value; infinities are spelled as Prometheus expects.
+fn number(value: f64) -> String {
+    match value {
+        f64::INFINITY => "+Inf".to_string(),
+        f64::NEG_INFINITY => "-Inf".to_string(),
+        _ => value.to_string(),
+    }
+}
+
+/// Escapes a label value: backslash, double quote and line feed.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::simulate::Simulator;
+    use serde_json::json;
+
+    async fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn serves_scanned_values() {
+        let modbus = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = Config::from_json(json!({
+            "server": { "protocol": "modbus", "host": "127.0.0.1",
+                        "port": modbus.local_addr().unwrap().port() },
+            "datapoints": [
+                { "name": "temp", "address": 0, "length": 1, "scale": 0.1, "unit": "°C",
+                  "simulate": { "generator": "constant", "value": 21.5 } },
+                { "name": "status", "address": 1, "length": 2, "data_type": "bitfield",
+                  "bitfields": [{ "name": "run", "bit": 17 }],
+                  "simulate": { "generator": "constant", "value": 131072 } },
+                { "name": "outputs", "address": 0, "length": 3, "register_type": "coil",
+                  "simulate": { "generator": "constant", "value": 5 } },
+            ],
+        }))
+        .unwrap();
+        let server = config.servers[0].clone();
+        let configs = config.datapoints_for(&server.name);
+        let simulator = Simulator::new(&server, configs.clone()).unwrap();
+        tokio::spawn(simulator.serve(modbus));
+        let mut scanner = Scanner::new(server, configs, 1);
+        scanner.scan_once().await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let metrics = Metrics::start(listener, 1);
+        metrics.update(0, &scanner);
+
+        let response = get(addr, "/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        let labels = |name: &str| format!("server=\"default\",unit=\"1\",datapoint=\"{}\"", name);
+        for line in [
+            format!("datapoint_tui_value{{{},uom=\"°C\"}} 21.5", labels("temp")),
+            format!(
+                "datapoint_tui_bit{{{},bit=\"17\",name=\"run\"}} 1",
+                labels("status")
+            ),
+            format!("datapoint_tui_datapoint_error{{{}}} 0", labels("outputs")),
+            "datapoint_tui_connected{server=\"default\"} 1".to_string(),
+            "datapoint_tui_scans_total{server=\"default\"} 1".to_string(),
+        ] {
+            assert!(
+                response.lines().any(|l| l == line),
+                "{} in\n{}",
+                line,
+                response
+            );
+        }
+        let values = response
+            .lines()
+            .filter(|l| l.starts_with("datapoint_tui_value{"))
+            .count();
+        assert_eq!(values, 1);
+
+        assert!(get(addr, "/other").await.starts_with("HTTP/1.1 404"));
+    }
+}
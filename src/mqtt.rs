@@ -0,0 +1,360 @@
+//! Publishing of datapoint values to an MQTT broker.
+//!
+//! Every datapoint is published as a JSON object to its own topic. The
+//! client queues messages while the broker is unreachable and reconnects
+//! in the background, so publishing never holds up a scan.
+
+use crate::config::{MqttConfig, PublishMode};
+use crate::datapoint::Datapoint;
+use crate::recorder::{json_value, timestamp};
+use crate::scanner::Scanner;
+use chrono::Local;
+use log::{info, warn};
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Messages queued for the broker before new ones are dropped
+const QUEUE_SIZE: usize = 1000;
+
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+/// Delay before reconnecting, doubled after each failure up to the maximum
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// Connection to the broker as shown in the header.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MqttStatus {
+    Connecting,
+    Connected,
+    /// Last connection error
+    Disconnected(String),
+}
+
+/// Handle shared by the scan loops and the UI.
+#[derive(Clone)]
+pub struct Mqtt {
+    client: AsyncClient,
+    config: Arc<MqttConfig>,
+    qos: QoS,
+    status: Arc<Mutex<MqttStatus>>,
+    /// Last published state per topic, for `publish: change`
+    published: Arc<Mutex<HashMap<String, Value>>>,
+}
+
+impl Mqtt {
+    /// Creates the client and starts its event loop. The connection is made
+    /// in the background.
+    pub fn start(config: MqttConfig) -> Self {
+        let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+        options.set_keep_alive(KEEP_ALIVE);
+        if let Some(ref username) = config.username {
+            options.set_credentials(username, config.password.as_deref().unwrap_or(""));
+        }
+        let (client, mut eventloop) = AsyncClient::new(options, QUEUE_SIZE);
+        let status = Arc::new(Mutex::new(MqttStatus::Connecting));
+        let published = Arc::new(Mutex::new(HashMap::new()));
+
+        let broker = format!("{}:{}", config.host, config.port);
+        let loop_status = status.clone();
+        let loop_published = published.clone();
+        tokio::spawn(async move {
+            let mut delay = RECONNECT_DELAY;
+            loop {
+                match eventloop.poll().await {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        info!("Connected to MQTT broker {}", broker);
+                        set_status(&loop_status, MqttStatus::Connected);
+                        // A restarted broker may have lost retained values,
+                        // so publish everything again
+                        if let Ok(mut published) = loop_published.lock() {
+                            published.clear();
+                        }
+                        delay = RECONNECT_DELAY;
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        let error = e.to_string();
+                        let changed = set_status(&loop_status, MqttStatus::Disconnected(error));
+                        if changed {
+                            warn!("MQTT broker {}: {}", broker, e);
+                        }
+                        tokio::time::sleep(delay).await;
+                        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+                    }
+                }
+            }
+        });
+
+        Self {
+            client,
+            qos: rumqttc::qos(config.qos).unwrap_or(QoS::AtMostOnce),
+            config: Arc::new(config),
+            status,
+            published,
+        }
+    }
+
+    pub fn status(&self) -> MqttStatus {
+        self.status
+            .lock()
+            .map(|status| status.clone())
+            .unwrap_or(MqttStatus::Connecting)
+    }
+
+    /// Queues the datapoints of a scanner after a scan, all of them or only
+    /// those that changed since they were last published.
+    pub fn publish(&self, scanner: &Scanner) {
+        let server = &scanner.server().name;
+        let now = timestamp(Local::now());
+        let Ok(mut published) = self.published.lock() else {
+            return;
+        };
+        for dp in scanner.get_datapoints() {
+            // Nothing to publish before the first read
+            if dp.value.is_none() && dp.error.is_none() {
+                continue;
+            }
+            let topic = self.topic(server, &dp.name);
+            let state = state(dp);
+            if self.config.publish == PublishMode::Change && published.get(&topic) == Some(&state) {
+                continue;
+            }
+
+            let mut payload = json!({ "timestamp": now });
+            if let (Some(payload), Some(fields)) = (payload.as_object_mut(), state.as_object()) {
+                payload.extend(fields.clone());
+            }
+            let sent = self.client.try_publish(
+                topic.as_str(),
+                self.qos,
+                self.config.retain,
+                payload.to_string(),
+            );
+            match sent {
+                Ok(()) => {
+                    published.insert(topic, state);
+                }
+                Err(e) => warn!("Dropping MQTT message for {}: {}", topic, e),
+            }
+        }
+    }
+
+    /// Topic from the template; wildcard characters in names are replaced.
+    fn topic(&self, server: &str, name: &str) -> String {
+        let clean = |text: &str| text.replace(['+', '#'], "_");
+        self.config
+            .topic
+            .replace("{server}", &clean(server))
+            .replace("{name}", &clean(name))
+    }
+}
+
+/// Returns whether the status changed.
+fn set_status(status: &Mutex<MqttStatus>, new: MqttStatus) -> bool {
+    let Ok(mut status) = status.lock() else {
+        return false;
+    };
+    let changed = *status != new;
+    *status = new;
+    changed
+}
+
+/// Published fields except the timestamp, compared for `publish: change`.
+fn state(dp: &Datapoint) -> Value {
+    json!({
+        "value": json_value(dp),
+        "text": dp.error.is_none().then(|| dp.display_value()).flatten(),
+        "unit": dp.unit,
+        "error": dp.error,
+        "alarm": dp.active_alarm().map(|alarm| alarm.describe()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::simulate::Simulator;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::mpsc;
+    use tokio::time::timeout;
+
+    /// Publish received by the broker: topic, QoS, retain flag and payload.
+    type Message = (String, u8, bool, Value);
+
+    /// Minimal MQTT 3.1.1 broker that accepts every client and forwards
+    /// the messages published to it.
+    async fn broker() -> (u16, mpsc::UnboundedReceiver<Message>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(client(stream, tx.clone()));
+            }
+        });
+        (port, rx)
+    }
+
+    async fn client(mut stream: TcpStream, tx: mpsc::UnboundedSender<Message>) {
+        loop {
+            let Ok(header) = stream.read_u8().await else {
+                return;
+            };
+            let mut length = 0usize;
+            for shift in (0..28).step_by(7) {
+                let byte = stream.read_u8().await.unwrap();
+                length |= ((byte & 0x7F) as usize) << shift;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+            }
+            let mut body = vec![0; length];
+            stream.read_exact(&mut body).await.unwrap();
+
+            match header >> 4 {
+                // CONNECT: accept
+                1 => stream.write_all(&[0x20, 2, 0, 0]).await.unwrap(),
+                // PUBLISH: acknowledge QoS 1 and forward
+                3 => {
+                    let qos = (header >> 1) & 3;
+                    let retain = header & 1 == 1;
+                    let topic_length = u16::from_be_bytes([body[0], body[1]]) as usize;
+                    let topic = String::from_utf8(body[2..2 + topic_length].to_vec()).unwrap();
+                    let mut payload = 2 + topic_length;
+                    if qos > 0 {
+                        let id = &body[payload..payload + 2];
+                        stream.write_all(&[0x40, 2, id[0], id[1]]).await.unwrap();
+                        payload += 2;
+                    }
+                    let payload = serde_json::from_slice(&body[payload..]).unwrap();
+                    let _ = tx.send((topic, qos, retain, payload));
+                }
+                // PINGREQ
+                12 => stream.write_all(&[0xD0, 0]).await.unwrap(),
+                // DISCONNECT
+                14 => return,
+                _ => {}
+            }
+        }
+    }
+
+    /// Scanner of a simulated server with a readable datapoint and one the
+    /// simulator does not serve.
+    async fn scanner() -> Scanner {
+        let modbus = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = Config::from_json(json!({
+            "server": { "name": "boiler", "protocol": "modbus", "host": "127.0.0.1",
+                        "port": modbus.local_addr().unwrap().port() },
+            "datapoints": [
+                { "name": "temp", "address": 0, "length": 1, "scale": 0.1, "unit": "°C",
+                  "simulate": { "generator": "constant", "value": 21.5 } },
+                { "name": "flow+", "address": 50, "length": 1 },
+            ],
+        }))
+        .unwrap();
+        let server = config.servers[0].clone();
+        let configs = config.datapoints_for(&server.name);
+        let simulator = Simulator::new(&server, configs[..1].to_vec()).unwrap();
+        tokio::spawn(simulator.serve(modbus));
+        Scanner::new(server, configs, 1)
+    }
+
+    async fn connect(port: u16, publish: PublishMode) -> Mqtt {
+        let mqtt = Mqtt::start(MqttConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            client_id: "test".to_string(),
+            username: None,
+            password: None,
+            topic: "site/{server}/{name}".to_string(),
+            qos: 1,
+            retain: true,
+            publish,
+        });
+        timeout(Duration::from_secs(5), async {
+            while mqtt.status() != MqttStatus::Connected {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        mqtt
+    }
+
+    /// Receives `count` messages, then checks that no more follow.
+    async fn receive(rx: &mut mpsc::UnboundedReceiver<Message>, count: usize) -> Vec<Message> {
+        let mut messages = Vec::new();
+        for _ in 0..count {
+            let message = timeout(Duration::from_secs(5), rx.recv()).await;
+            messages.push(message.unwrap().unwrap());
+        }
+        let extra = timeout(Duration::from_millis(300), rx.recv()).await;
+        assert!(extra.is_err(), "unexpected {:?}", extra);
+        messages.sort_by(|a, b| a.0.cmp(&b.0));
+        messages
+    }
+
+    #[tokio::test]
+    async fn publishes_changes() {
+        let (port, mut rx) = broker().await;
+        let mqtt = connect(port, PublishMode::Change).await;
+        let mut scanner = scanner().await;
+        scanner.scan_once().await.unwrap();
+        mqtt.publish(&scanner);
+
+        let messages = receive(&mut rx, 2).await;
+        let topics: Vec<(&str, u8, bool)> = messages
+            .iter()
+            .map(|(topic, qos, retain, _)| (topic.as_str(), *qos, *retain))
+            .collect();
+        assert_eq!(
+            topics,
+            [
+                ("site/boiler/flow_", 1, true),
+                ("site/boiler/temp", 1, true)
+            ]
+        );
+
+        let failed = &messages[0].3;
+        assert_eq!(failed["value"], Value::Null);
+        assert_eq!(failed["text"], Value::Null);
+        assert!(failed["error"].is_string(), "{}", failed);
+
+        let temp = &messages[1].3;
+        assert_eq!(temp["value"], json!(21.5));
+        assert_eq!(temp["unit"], json!("°C"));
+        assert_eq!(temp["error"], Value::Null);
+        assert_eq!(temp["alarm"], Value::Null);
+        let stamp = temp["timestamp"].as_str().unwrap();
+        assert!(
+            chrono::DateTime::parse_from_rfc3339(stamp).is_ok(),
+            "{}",
+            stamp
+        );
+
+        // Nothing changed
+        scanner.scan_once().await.unwrap();
+        mqtt.publish(&scanner);
+        receive(&mut rx, 0).await;
+    }
+
+    #[tokio::test]
+    async fn publishes_every_scan() {
+        let (port, mut rx) = broker().await;
+        let mqtt = connect(port, PublishMode::EveryScan).await;
+        let mut scanner = scanner().await;
+        for _ in 0..2 {
+            scanner.scan_once().await.unwrap();
+            mqtt.publish(&scanner);
+            let messages = receive(&mut rx, 2).await;
+            assert_eq!(messages[1].0, "site/boiler/temp");
+            assert_eq!(messages[1].3["value"], json!(21.5));
+        }
+    }
+}
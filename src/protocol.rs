@@ -0,0 +1,5 @@
+//! Modbus protocol constants that tokio-modbus does not provide.
+
+/// Function code and MEI type of Read Device Identification
+pub const ENCAPSULATED_INTERFACE: u8 = 0x2B;
+pub const READ_DEVICE_ID: u8 = 0x0E;
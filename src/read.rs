@@ -0,0 +1,286 @@
+//! `read` subcommand: a single scan printed for scripts and CI jobs.
+//!
+//! The exit code tells the caller whether everything is fine: 1 if any
+//! datapoint could not be read, 2 if an alarm is active, 0 otherwise.
+
+use crate::config::{Config, DataType, DatapointConfig};
+use crate::datapoint::Datapoint;
+use crate::recorder::{csv_field, json_value, raw_words, timestamp};
+use crate::scanner::Scanner;
+use anyhow::{Context, Result};
+use chrono::Local;
+use clap::{Args, ValueEnum};
+use serde_json::{Map, Value, json};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+#[derive(Args, Debug)]
+pub struct ReadArgs {
+    /// Only read the named datapoints (repeatable)
+    #[arg(short, long, value_name = "NAME")]
+    name: Vec<String>,
+
+    #[arg(short, long, value_enum, default_value = "table")]
+    format: OutputFormat,
+
+    /// Read from this Modbus TCP server instead of the configured datapoints
+    #[arg(long, requires = "address", conflicts_with = "name")]
+    host: Option<String>,
+
+    #[arg(long, default_value_t = 502, requires = "host")]
+    port: u16,
+
+    #[arg(long, default_value_t = 1, requires = "host")]
+    unit: u8,
+
+    /// First register or bit address of an ad-hoc read
+    #[arg(long, requires = "host")]
+    address: Option<u16>,
+
+    /// u16, i16, u32, i32, f32, u64, i64, f64, bitfield or string
+    #[arg(long = "type", value_name = "TYPE", default_value = "u16")]
+    data_type: String,
+
+    /// Registers per value [default: the size of the type]
+    #[arg(long, requires = "host")]
+    length: Option<u16>,
+
+    /// holding, input, coil or discrete_input
+    #[arg(long, default_value = "holding")]
+    register_type: String,
+
+    /// ABCD, CDAB, BADC or DCBA
+    #[arg(long)]
+    byte_order: Option<String>,
+
+    /// Number of consecutive values to read
+    #[arg(long, default_value_t = 1, requires = "host")]
+    count: u16,
+}
+
+/// Scans every server once, prints the values and returns the exit code.
+pub async fn run(config_path: &str, args: &ReadArgs) -> Result<i32> {
+    let mut config = match (&args.host, args.address) {
+        (Some(host), Some(address)) => adhoc_config(host, address, args)?,
+        _ => Config::load(config_path)?,
+    };
+    if !args.name.is_empty() {
+        select(&mut config, &args.name)?;
+    }
+
+    let columns = config.column_names();
+    let mut datapoints = Vec::new();
+    for server in &config.servers {
+        let configs = config.datapoints_for(&server.name);
+        if configs.is_empty() {
+            continue;
+        }
+        let mut scanner = Scanner::new(server.clone(), configs, 1);
+        // Failures are reported per datapoint
+        let _ = scanner.scan_once().await;
+        datapoints.extend(scanner.get_datapoints().iter().cloned());
+    }
+    let read: Vec<(String, Datapoint)> = columns.into_iter().zip(datapoints).collect();
+
+    match args.format {
+        OutputFormat::Table => print_table(&read),
+        OutputFormat::Json => print_json(&read)?,
+        OutputFormat::Csv => print_csv(&read),
+    }
+
+    let code = if read.iter().any(|(_, dp)| failure(dp).is_some()) {
+        1
+    } else if read.iter().any(|(_, dp)| dp.active_alarm().is_some()) {
+        2
+    } else {
+        0
+    };
+    Ok(code)
+}
+
+/// Configuration for `count` consecutive values at `address`, named by
+/// their address.
+fn adhoc_config(host: &str, address: u16, args: &ReadArgs) -> Result<Config> {
+    let data_type: DataType = serde_json::from_value(json!(args.data_type))
+        .with_context(|| format!("Invalid --type '{}'", args.data_type))?;
+    let length = args.length.unwrap_or(data_type.register_count());
+
+    let datapoints: Vec<Value> = (0..args.count)
+        .map(|i| {
+            let address = address.saturating_add(i.saturating_mul(length));
+            json!({
+                "name": address.to_string(),
+                "address": address,
+                "length": length,
+                "data_type": args.data_type,
+                "register_type": args.register_type,
+                "byte_order": args.byte_order,
+            })
+        })
+        .collect();
+
+    Config::from_json(json!({
+        "servers": [{
+            "protocol": "modbus",
+            "host": host,
+            "port": args.port,
+            "unit_id": args.unit,
+        }],
+        "datapoints": datapoints,
+    }))
+}
+
+/// Keeps only the named datapoints, given by name or as `server/name`.
+fn select(config: &mut Config, names: &[String]) -> Result<()> {
+    let default = config
+        .servers
+        .first()
+        .map(|s| s.name.clone())
+        .unwrap_or_default();
+    let matches = |dp: &DatapointConfig, name: &str| {
+        let server = dp.server.as_deref().unwrap_or(&default);
+        name == dp.name || name == format!("{}/{}", server, dp.name)
+    };
+    if let Some(name) = names
+        .iter()
+        .find(|name| !config.datapoints.iter().any(|dp| matches(dp, name)))
+    {
+        anyhow::bail!("No datapoint named '{}'", name);
+    }
+    config
+        .datapoints
+        .retain(|dp| names.iter().any(|name| matches(dp, name)));
+    Ok(())
+}
+
+/// Why the datapoint has no value, if it has none. A datapoint without a
+/// value or error was never read, e.g. because the connection failed.
+fn failure(dp: &Datapoint) -> Option<&str> {
+    match (&dp.error, &dp.value) {
+        (Some(error), _) => Some(error),
+        (None, None) => Some("Not read"),
+        (None, Some(_)) => None,
+    }
+}
+
+/// Value with unit and enum label as shown in the UI.
+fn value_text(dp: &Datapoint) -> String {
+    match failure(dp) {
+        Some(_) => "-".to_string(),
+        None => dp.display_value().unwrap_or_else(|| "-".to_string()),
+    }
+}
+
+fn status_text(dp: &Datapoint) -> String {
+    match (failure(dp), dp.active_alarm()) {
+        (Some(error), _) => format!("ERROR: {}", error),
+        (None, Some(alarm)) => format!("{} ({})", alarm.label(), alarm.describe()),
+        (None, None) => "OK".to_string(),
+    }
+}
+
+fn print_table(read: &[(String, Datapoint)]) {
+    let rows: Vec<[String; 3]> = read
+        .iter()
+        .map(|(name, dp)| [name.clone(), value_text(dp), status_text(dp)])
+        .collect();
+    let width = |column: usize, title: &str| {
+        rows.iter()
+            .map(|row| row[column].chars().count())
+            .chain([title.len()])
+            .max()
+            .unwrap_or(0)
+    };
+    let (name_width, value_width) = (width(0, "NAME"), width(1, "VALUE"));
+
+    println!("{:<name_width$}  {:<value_width$}  STATUS", "NAME", "VALUE");
+    for [name, value, status] in rows {
+        println!("{:<name_width$}  {:<value_width$}  {}", name, value, status);
+    }
+}
+
+fn print_json(read: &[(String, Datapoint)]) -> Result<()> {
+    let mut datapoints = Map::new();
+    for (name, dp) in read {
+        datapoints.insert(
+            name.clone(),
+            json!({
+                "value": json_value(dp),
+                "text": failure(dp).is_none().then(|| dp.display_value()).flatten(),
+                "raw": dp.registers,
+                "error": failure(dp),
+                "alarm": dp.active_alarm().map(|alarm| alarm.describe()),
+                "severity": dp.active_alarm().map(|alarm| alarm.severity()),
+            }),
+        );
+    }
+    let output = json!({
+        "timestamp": timestamp(Local::now()),
+        "datapoints": datapoints,
+    });
+    println!("{}", serde_json::to_string_pretty(&output)?);
+    Ok(())
+}
+
+fn print_csv(read: &[(String, Datapoint)]) {
+    println!("name,value,text,raw,error,alarm");
+    for (name, dp) in read {
+        let value = match json_value(dp) {
+            Some(Value::String(text)) => csv_field(&text),
+            Some(value) => value.to_string(),
+            None => String::new(),
+        };
+        let text = match failure(dp) {
+            Some(_) => String::new(),
+            None => dp.display_value().unwrap_or_default(),
+        };
+        println!(
+            "{},{},{},{},{},{}",
+            csv_field(name),
+            value,
+            csv_field(&text),
+            raw_words(&dp.registers),
+            csv_field(failure(dp).unwrap_or_default()),
+            csv_field(&dp.active_alarm().map(|a| a.describe()).unwrap_or_default()),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn adhoc_args(host: &str, port: u16) -> ReadArgs {
+        ReadArgs {
+            name: Vec::new(),
+            format: OutputFormat::Table,
+            host: Some(host.to_string()),
+            port,
+            unit: 1,
+            address: Some(0),
+            data_type: "u16".to_string(),
+            length: None,
+            register_type: "holding".to_string(),
+            byte_order: None,
+            count: 2,
+        }
+    }
+
+    #[tokio::test]
+    async fn unreachable_host_fails() {
+        // A port nobody listens on any more
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        for host in ["127.0.0.1", "localhost", "unresolvable.invalid"] {
+            let code = run("unused.yaml", &adhoc_args(host, port)).await.unwrap();
+            assert_eq!(code, 1, "{}", host);
+        }
+    }
+}
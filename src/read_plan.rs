@@ -0,0 +1,219 @@
+use crate::config::{DatapointConfig, RegisterType};
+
+/// Modbus limit for a single FC3/FC4 request.
+pub const MAX_REGISTERS_PER_READ: u16 = 125;
+
+/// One read request covering one or more datapoints of the same register type.
+/// For coils and discrete inputs, `address` and `count` are in bits.
+#[derive(Debug, Clone)]
+pub struct ReadBlock {
+    pub unit_id: u8,
+    pub register_type: RegisterType,
+    pub address: u16,
+    pub count: u16,
+    /// Indices into the configured datapoint list served by this block
+    pub members: Vec<usize>,
+}
+
+impl ReadBlock {
+    fn single(index: usize, dp: &DatapointConfig, unit_id: u8) -> Self {
+        Self {
+            unit_id,
+            register_type: dp.register_type,
+            address: dp.address,
+            count: dp.length,
+            members: vec![index],
+        }
+    }
+
+    fn end(&self) -> u32 {
+        self.address as u32 + self.count as u32
+    }
+
+    /// Splits the block into one block per datapoint.
+    pub fn split(&self, datapoints: &[DatapointConfig]) -> Vec<ReadBlock> {
+        self.members
+            .iter()
+            .map(|&i| ReadBlock::single(i, &datapoints[i], self.unit_id))
+            .collect()
+    }
+
+    /// Returns the registers belonging to `dp` out of the block read result.
+    pub fn slice<'a>(&self, dp: &DatapointConfig, registers: &'a [u16]) -> &'a [u16] {
+        let start = (dp.address - self.address) as usize;
+        let end = (start + dp.length as usize).min(registers.len());
+        registers.get(start..end).unwrap_or(&[])
+    }
+}
+
+/// Groups datapoints of the same unit ID and register type into blocks.
+/// Datapoints without a `unit_id` override belong to `default_unit`.
+///
+/// Datapoints are merged while the gap to the previous one is at most
+/// `max_gap` registers and the block stays within `max_block_size` (capped
+/// by the protocol limit of the register type). Datapoints longer than that
+/// get a block of their own.
+pub fn plan_reads(
+    datapoints: &[DatapointConfig],
+    default_unit: u8,
+    max_gap: u16,
+    max_block_size: u16,
+) -> Vec<ReadBlock> {
+    let mut order: Vec<usize> = (0..datapoints.len()).collect();
+    let unit_of = |dp: &DatapointConfig| dp.unit_id.unwrap_or(default_unit);
+    order.sort_by_key(|&i| {
+        (
+            unit_of(&datapoints[i]),
+            datapoints[i].register_type as u8,
+            datapoints[i].address,
+            datapoints[i].length,
+        )
+    });
+
+    let mut blocks: Vec<ReadBlock> = Vec::new();
+    for i in order {
+        let dp = &datapoints[i];
+        let dp_end = dp.address as u32 + dp.length as u32;
+        let max_block_size = max_block_size.clamp(1, dp.register_type.max_read_quantity()) as u32;
+
+        let unit_id = unit_of(dp);
+
+        if let Some(block) = blocks.last_mut()
+            && block.unit_id == unit_id
+            && block.register_type == dp.register_type
+            && dp.address as u32 <= block.end() + max_gap as u32
+            && dp_end.max(block.end()) - block.address as u32 <= max_block_size
+        {
+            block.count = (dp_end.max(block.end()) - block.address as u32) as u16;
+            block.members.push(i);
+            continue;
+        }
+
+        blocks.push(ReadBlock::single(i, dp, unit_id));
+    }
+
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+
+    fn dp(address: u16, length: u16, extra: Value) -> DatapointConfig {
+        let mut value =
+            json!({ "name": format!("dp{}", address), "address": address, "length": length });
+        if let (Some(value), Some(extra)) = (value.as_object_mut(), extra.as_object()) {
+            value.extend(extra.clone());
+        }
+        serde_json::from_value(value).unwrap()
+    }
+
+    /// Address, count and members of each block.
+    fn blocks(plan: &[ReadBlock]) -> Vec<(u16, u16, Vec<usize>)> {
+        plan.iter()
+            .map(|b| (b.address, b.count, b.members.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn merges_across_small_gaps() {
+        let datapoints = [
+            dp(10, 1, json!({})),
+            dp(0, 2, json!({})),
+            dp(3, 2, json!({})),
+            dp(4, 1, json!({})),
+        ];
+        // Gap of one register between 0-1 and 3-4; 10 is six further
+        assert_eq!(
+            blocks(&plan_reads(&datapoints, 1, 1, 125)),
+            [(0, 5, vec![1, 2, 3]), (10, 1, vec![0])]
+        );
+        assert_eq!(
+            blocks(&plan_reads(&datapoints, 1, 5, 125)),
+            [(0, 11, vec![1, 2, 3, 0])]
+        );
+        assert_eq!(
+            blocks(&plan_reads(&datapoints, 1, 0, 125)),
+            [(0, 2, vec![1]), (3, 2, vec![2, 3]), (10, 1, vec![0])]
+        );
+        // A block size of 1 disables batching
+        assert_eq!(plan_reads(&datapoints, 1, 5, 1).len(), 4);
+    }
+
+    #[test]
+    fn keeps_to_the_protocol_limits() {
+        let registers = [
+            dp(0, 100, json!({})),
+            dp(100, 25, json!({})),
+            dp(125, 1, json!({})),
+        ];
+        assert_eq!(
+            blocks(&plan_reads(&registers, 1, 0, u16::MAX)),
+            [(0, 125, vec![0, 1]), (125, 1, vec![2])]
+        );
+        assert_eq!(
+            blocks(&plan_reads(&registers, 1, 0, 110)),
+            [(0, 100, vec![0]), (100, 26, vec![1, 2])]
+        );
+
+        let coil = json!({ "register_type": "coil" });
+        let coils = [
+            dp(0, 1500, coil.clone()),
+            dp(1500, 500, coil.clone()),
+            dp(2000, 8, coil),
+        ];
+        assert_eq!(
+            blocks(&plan_reads(&coils, 1, 0, u16::MAX)),
+            [(0, 2000, vec![0, 1]), (2000, 8, vec![2])]
+        );
+
+        // Too long for one request, but read as configured
+        let long = [dp(0, 130, json!({})), dp(130, 1, json!({}))];
+        assert_eq!(
+            blocks(&plan_reads(&long, 1, 0, 125)),
+            [(0, 130, vec![0]), (130, 1, vec![1])]
+        );
+    }
+
+    #[test]
+    fn separates_units_and_register_types() {
+        let datapoints = [
+            dp(0, 1, json!({})),
+            dp(1, 1, json!({ "unit_id": 2 })),
+            dp(2, 1, json!({ "register_type": "input" })),
+            dp(3, 1, json!({ "unit_id": 1 })),
+        ];
+        let plan = plan_reads(&datapoints, 1, 10, 125);
+        let keys: Vec<(u8, RegisterType, Vec<usize>)> = plan
+            .iter()
+            .map(|b| (b.unit_id, b.register_type, b.members.clone()))
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                (1, RegisterType::Holding, vec![0, 3]),
+                (1, RegisterType::Input, vec![2]),
+                (2, RegisterType::Holding, vec![1]),
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_and_slices_blocks() {
+        let datapoints = [dp(5, 2, json!({})), dp(8, 1, json!({}))];
+        let plan = plan_reads(&datapoints, 3, 1, 125);
+        assert_eq!(blocks(&plan), [(5, 4, vec![0, 1])]);
+
+        let words = [50, 51, 52, 53];
+        assert_eq!(plan[0].slice(&datapoints[0], &words), [50, 51]);
+        assert_eq!(plan[0].slice(&datapoints[1], &words), [53]);
+        // A short response leaves later datapoints empty
+        assert_eq!(plan[0].slice(&datapoints[0], &words[..1]), [50]);
+        assert!(plan[0].slice(&datapoints[1], &words[..2]).is_empty());
+
+        let singles = plan[0].split(&datapoints);
+        assert_eq!(blocks(&singles), [(5, 2, vec![0]), (8, 1, vec![1])]);
+        assert!(singles.iter().all(|b| b.unit_id == 3));
+    }
+}
@@ -0,0 +1,629 @@
+//! Recording of scanned values to CSV or JSON Lines files.
+//!
+//! Scan loops hand a snapshot of their datapoints to [`Recorder::record`],
+//! which only queues it; the file is written by a separate blocking task so
+//! a slow disk never holds up a scan. Files are rotated by size or age by
+//! renaming the current file with a timestamp suffix.
+
+use crate::datapoint::{DataValue, Datapoint};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, SecondsFormat};
+use clap::ValueEnum;
+use log::{info, warn};
+use serde_json::{Map, Value, json};
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Scans queued for the writer before new ones are dropped
+const QUEUE_SIZE: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RecordFormat {
+    /// One row per scan and three columns (value, raw, error) per datapoint
+    Csv,
+    /// One JSON object per scan
+    Jsonl,
+}
+
+impl RecordFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            RecordFormat::Csv => "csv",
+            RecordFormat::Jsonl => "jsonl",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordOptions {
+    pub path: PathBuf,
+    pub format: RecordFormat,
+    /// Rotate once the file reaches this many bytes
+    pub max_size: Option<u64>,
+    /// Rotate once the file has been written for this long
+    pub max_age: Option<Duration>,
+}
+
+impl RecordOptions {
+    /// JSON Lines on standard output, as printed by `--headless`.
+    pub fn stdout() -> Self {
+        Self {
+            path: PathBuf::from("-"),
+            format: RecordFormat::Jsonl,
+            max_size: None,
+            max_age: None,
+        }
+    }
+
+    fn is_stdout(&self) -> bool {
+        self.path.as_os_str() == "-"
+    }
+}
+
+/// Datapoint state at the end of a scan.
+struct RecordedValue {
+    value: Option<Value>,
+    raw: Vec<u16>,
+    error: Option<String>,
+}
+
+pub struct ScanRecord {
+    timestamp: DateTime<Local>,
+    server: String,
+    /// Position of the server's first datapoint among all columns
+    first_column: usize,
+    values: Vec<RecordedValue>,
+}
+
+impl ScanRecord {
+    pub fn new(
+        timestamp: DateTime<Local>,
+        server: &str,
+        first_column: usize,
+        datapoints: &[Datapoint],
+    ) -> Self {
+        Self {
+            timestamp,
+            server: server.to_string(),
+            first_column,
+            values: datapoints.iter().map(recorded_value).collect(),
+        }
+    }
+}
+
+/// Handle shared by the scan loops and the UI.
+#[derive(Clone)]
+pub struct Recorder {
+    tx: mpsc::Sender<ScanRecord>,
+    enabled: Arc<AtomicBool>,
+    path: PathBuf,
+}
+
+impl Recorder {
+    /// Starts the writer task. `columns` are the names of all datapoints in
+    /// display order; nothing is written until recording is enabled.
+    pub fn start(options: RecordOptions, columns: Vec<String>, enabled: bool) -> Self {
+        let (tx, mut rx) = mpsc::channel(QUEUE_SIZE);
+        let enabled = Arc::new(AtomicBool::new(enabled));
+        let path = options.path.clone();
+
+        let writer_enabled = enabled.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut writer = RecordWriter::new(options, columns);
+            while let Some(record) = rx.blocking_recv() {
+                if let Err(e) = writer.write(&record) {
+                    warn!("Recording stopped: {:#}", e);
+                    writer_enabled.store(false, Ordering::Relaxed);
+                    writer.close();
+                }
+            }
+        });
+
+        Self { tx, enabled, path }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn toggle(&self) {
+        self.enabled.fetch_xor(true, Ordering::Relaxed);
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// Queues the state of one server's datapoints after a scan. Never
+    /// waits; the scan is dropped if the writer is behind.
+    pub fn record(&self, server: &str, first_column: usize, datapoints: &[Datapoint]) {
+        if !self.is_enabled() {
+            return;
+        }
+        let record = ScanRecord::new(Local::now(), server, first_column, datapoints);
+        if self.tx.try_send(record).is_err() {
+            warn!("Recorder is behind, dropping scan of {}", server);
+        }
+    }
+}
+
+/// Value as recorded: the engineering value of numbers, the text of strings
+/// and the bits of coil ranges. `None` while the datapoint has an error.
+pub fn json_value(dp: &Datapoint) -> Option<Value> {
+    match dp.value {
+        _ if dp.error.is_some() => None,
+        None => None,
+        Some(DataValue::String(ref text)) => Some(json!(text)),
+        Some(DataValue::Bits(_)) => dp.display_value().map(Value::from),
+        Some(_) => dp.engineering_value().map(number),
+    }
+}
+
+fn recorded_value(dp: &Datapoint) -> RecordedValue {
+    RecordedValue {
+        value: json_value(dp),
+        raw: dp.registers.clone(),
+        error: dp.error.clone(),
+    }
+}
+
+/// Writes scans to the recording file, rotating it as configured.
+pub struct RecordWriter {
+    options: RecordOptions,
+    columns: Vec<String>,
+    file: Option<Box<dyn Write + Send>>,
+    size: u64,
+    opened_at: DateTime<Local>,
+}
+
+impl RecordWriter {
+    pub fn new(options: RecordOptions, columns: Vec<String>) -> Self {
+        Self {
+            options,
+            columns,
+            file: None,
+            size: 0,
+            opened_at: Local::now(),
+        }
+    }
+
+    pub fn write(&mut self, record: &ScanRecord) -> Result<()> {
+        if self.file.is_some() && self.needs_rotation(record.timestamp) {
+            self.rotate()?;
+        }
+        if self.file.is_none() {
+            self.open()?;
+        }
+
+        let line = match self.options.format {
+            RecordFormat::Csv => self.csv_row(record),
+            RecordFormat::Jsonl => self.json_line(record),
+        };
+        self.write_line(&line)
+    }
+
+    fn needs_rotation(&self, now: DateTime<Local>) -> bool {
+        if self.options.is_stdout() {
+            return false;
+        }
+        let too_big = self.options.max_size.is_some_and(|max| self.size >= max);
+        let too_old = self
+            .options
+            .max_age
+            .is_some_and(|max| (now - self.opened_at).to_std().unwrap_or_default() >= max);
+        too_big || too_old
+    }
+
+    /// Opens the recording file for appending, writing the CSV header if
+    /// the file is new.
+    fn open(&mut self) -> Result<()> {
+        let path = &self.options.path;
+        if self.options.is_stdout() {
+            self.size = 0;
+            self.file = Some(Box::new(io::stdout()));
+        } else {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Failed to open recording file {}", path.display()))?;
+            self.size = file.metadata().map(|m| m.len()).unwrap_or(0);
+            self.file = Some(Box::new(BufWriter::new(file)));
+            info!("Recording to {}", path.display());
+        }
+        self.opened_at = Local::now();
+
+        if self.size == 0 && self.options.format == RecordFormat::Csv {
+            let mut header = vec!["timestamp".to_string(), "server".to_string()];
+            for column in &self.columns {
+                header.push(csv_field(column));
+                header.push(csv_field(&format!("{} raw", column)));
+                header.push(csv_field(&format!("{} error", column)));
+            }
+            self.write_line(&header.join(","))?;
+        }
+        Ok(())
+    }
+
+    /// Moves the current file aside as `<name>-<timestamp>.<ext>`, or
+    /// `<name>-<timestamp>-<n>.<ext>` if that is taken.
+    fn rotate(&mut self) -> Result<()> {
+        self.close();
+        let path = &self.options.path;
+        let stem = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let name = format!("{}-{}", stem, Local::now().format("%Y%m%d-%H%M%S"));
+        let ext = path
+            .extension()
+            .map(|ext| format!(".{}", ext.to_string_lossy()))
+            .unwrap_or_default();
+        // Rotations within the same second get a counter rather than
+        // replacing the previous file
+        let mut rotated = path.with_file_name(format!("{}{}", name, ext));
+        let mut n = 1;
+        while rotated.exists() {
+            rotated = path.with_file_name(format!("{}-{}{}", name, n, ext));
+            n += 1;
+        }
+        fs::rename(path, &rotated).with_context(|| {
+            format!(
+                "Failed to rotate {} to {}",
+                path.display(),
+                rotated.display()
+            )
+        })?;
+        info!("Rotated recording to {}", rotated.display());
+        Ok(())
+    }
+
+    pub fn close(&mut self) {
+        if let Some(mut file) = self.file.take() {
+            let _ = file.flush();
+        }
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        let Some(ref mut file) = self.file else {
+            return Ok(());
+        };
+        writeln!(file, "{}", line)
+            .and_then(|_| file.flush())
+            .context("Failed to write recording")?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    /// Row with the server's columns filled in and the other servers'
+    /// columns left empty.
+    fn csv_row(&self, record: &ScanRecord) -> String {
+        let mut cells = vec![String::new(); 2 + self.columns.len() * 3];
+        cells[0] = timestamp(record.timestamp);
+        cells[1] = csv_field(&record.server);
+        for (i, value) in record.values.iter().enumerate() {
+            let column = 2 + (record.first_column + i) * 3;
+            if column + 2 >= cells.len() {
+                break;
+            }
+            cells[column] = match value.value {
+                Some(Value::String(ref text)) => csv_field(text),
+                Some(ref value) => value.to_string(),
+                None => String::new(),
+            };
+            cells[column + 1] = raw_words(&value.raw);
+            cells[column + 2] = value.error.as_deref().map(csv_field).unwrap_or_default();
+        }
+        cells.join(",")
+    }
+
+    fn json_line(&self, record: &ScanRecord) -> String {
+        let mut datapoints = Map::new();
+        for (i, value) in record.values.iter().enumerate() {
+            let Some(name) = self.columns.get(record.first_column + i) else {
+                break;
+            };
+            datapoints.insert(
+                name.clone(),
+                json!({
+                    "value": value.value,
+                    "raw": value.raw,
+                    "error": value.error,
+                }),
+            );
+        }
+        json!({
+            "timestamp": timestamp(record.timestamp),
+            "server": record.server,
+            "datapoints": datapoints,
+        })
+        .to_string()
+    }
+}
+
+/// Whole numbers are written without a fraction, e.g. `12` rather than `12.0`.
+fn number(value: f64) -> Value {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        Value::from(value as i64)
+    } else {
+        Value::from(value)
+    }
+}
+
+pub fn timestamp(time: DateTime<Local>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, false)
+}
+
+pub fn raw_words(raw: &[u16]) -> String {
+    raw.iter()
+        .map(|w| format!("{:04X}", w))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Quotes a CSV field if it contains a separator, quote or line break.
+pub fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Parses a size like `500000`, `64K`, `10M` or `1G` into bytes.
+pub fn parse_size(input: &str) -> Result<u64, String> {
+    let input = input.trim();
+    let (number, factor) = match input.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&input[..i], 1 << 10),
+        Some((i, 'm' | 'M')) => (&input[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&input[..i], 1 << 30),
+        _ => (input, 1),
+    };
+    number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .filter(|&n| n > 0)
+        .map(|n| n * factor)
+        .ok_or_else(|| format!("invalid size '{}', expected e.g. 10M", input))
+}
+
+/// Parses a duration like `90s`, `30m`, `1h` or `1d`.
+pub fn parse_duration(input: &str) -> Result<Duration, String> {
+    let input = input.trim();
+    let (number, unit) = input.split_at(input.len().saturating_sub(1));
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return Err(format!("invalid duration '{}', expected e.g. 1h", input)),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .filter(|&n| n > 0)
+        .map(|n| Duration::from_secs(n * seconds))
+        .ok_or_else(|| format!("invalid duration '{}', expected e.g. 1h", input))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "datapoint_tui_{}_recorder_{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn writer(path: PathBuf, format: RecordFormat) -> RecordWriter {
+        let options = RecordOptions {
+            path,
+            format,
+            max_size: None,
+            max_age: None,
+        };
+        let columns = ["temp", "label, quoted \"x\"", "speed"].map(String::from);
+        RecordWriter::new(options, columns.to_vec())
+    }
+
+    fn value(value: Option<Value>, raw: &[u16], error: Option<&str>) -> RecordedValue {
+        RecordedValue {
+            value,
+            raw: raw.to_vec(),
+            error: error.map(String::from),
+        }
+    }
+
+    /// Scan of the first server (temp and label) at `time`.
+    fn first_server(time: DateTime<Local>) -> ScanRecord {
+        ScanRecord {
+            timestamp: time,
+            server: "plc".to_string(),
+            first_column: 0,
+            values: vec![
+                value(Some(json!(21.5)), &[215], None),
+                value(Some(json!("on, \"auto\"")), &[0x6F6E], None),
+            ],
+        }
+    }
+
+    /// Scan of the second server (speed), which failed.
+    fn second_server(time: DateTime<Local>) -> ScanRecord {
+        ScanRecord {
+            timestamp: time,
+            server: "drive".to_string(),
+            first_column: 2,
+            values: vec![value(None, &[], Some("timeout"))],
+        }
+    }
+
+    fn lines(path: &Path) -> Vec<String> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    /// Rotated files next to `path`, sorted by name.
+    fn rotated(path: &Path) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|p| p != path)
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn writes_csv_rows_per_server() {
+        let path = temp_dir("csv").join("scans.csv");
+        let mut writer = writer(path.clone(), RecordFormat::Csv);
+        let time = Local::now();
+        writer.write(&first_server(time)).unwrap();
+        writer.write(&second_server(time)).unwrap();
+        writer.close();
+
+        let stamp = timestamp(time);
+        assert_eq!(
+            lines(&path),
+            [
+                "timestamp,server,temp,temp raw,temp error,\
+                 \"label, quoted \"\"x\"\"\",\"label, quoted \"\"x\"\" raw\",\
+                 \"label, quoted \"\"x\"\" error\",speed,speed raw,speed error"
+                    .to_string(),
+                format!("{},plc,21.5,00D7,,\"on, \"\"auto\"\"\",6F6E,,,,", stamp),
+                format!("{},drive,,,,,,,,,timeout", stamp),
+            ]
+        );
+
+        // Appending to an existing file does not repeat the header
+        let mut writer = self::writer(path.clone(), RecordFormat::Csv);
+        writer.write(&second_server(time)).unwrap();
+        writer.close();
+        let lines = lines(&path);
+        assert_eq!(lines.len(), 4);
+        assert!(lines[3].ends_with(",drive,,,,,,,,,timeout"));
+    }
+
+    #[test]
+    fn writes_json_lines() {
+        let path = temp_dir("jsonl").join("scans.jsonl");
+        let mut writer = writer(path.clone(), RecordFormat::Jsonl);
+        let time = Local::now();
+        writer.write(&first_server(time)).unwrap();
+        writer.write(&second_server(time)).unwrap();
+        writer.close();
+
+        let records: Vec<Value> = lines(&path)
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            records,
+            [
+                json!({
+                    "timestamp": timestamp(time),
+                    "server": "plc",
+                    "datapoints": {
+                        "temp": { "value": 21.5, "raw": [215], "error": null },
+                        "label, quoted \"x\"": {
+                            "value": "on, \"auto\"", "raw": [0x6F6E], "error": null
+                        },
+                    },
+                }),
+                json!({
+                    "timestamp": timestamp(time),
+                    "server": "drive",
+                    "datapoints": {
+                        "speed": { "value": null, "raw": [], "error": "timeout" },
+                    },
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn rotates_by_size() {
+        let path = temp_dir("size").join("scans.csv");
+        let mut writer = writer(path.clone(), RecordFormat::Csv);
+        writer.options.max_size = Some(1);
+        let time = Local::now();
+        for _ in 0..3 {
+            writer.write(&first_server(time)).unwrap();
+        }
+        writer.close();
+
+        // Every write after the first finds the file full; rotations in the
+        // same second are numbered rather than overwritten
+        let files = rotated(&path);
+        assert_eq!(files.len(), 2, "{:?}", files);
+        for file in files.iter().chain([&path]) {
+            let lines = lines(file);
+            assert_eq!(lines.len(), 2, "{}", file.display());
+            assert!(lines[0].starts_with("timestamp,server,temp,"));
+            assert!(lines[1].contains(",plc,21.5,"));
+        }
+
+        let names: Vec<String> = files
+            .iter()
+            .map(|f| f.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        let first = names.iter().min_by_key(|name| name.len()).unwrap();
+        let stem = first.strip_suffix(".csv").unwrap();
+        // scans-YYYYmmdd-HHMMSS.csv
+        let stamp = stem.strip_prefix("scans-").unwrap();
+        assert_eq!(stamp.len(), 15);
+        assert!(chrono::NaiveDateTime::parse_from_str(stamp, "%Y%m%d-%H%M%S").is_ok());
+        assert!(names.contains(&format!("{}-1.csv", stem)), "{:?}", names);
+    }
+
+    #[test]
+    fn rotates_by_age() {
+        let path = temp_dir("age").join("scans.jsonl");
+        let mut writer = writer(path.clone(), RecordFormat::Jsonl);
+        writer.options.max_age = Some(Duration::from_secs(60));
+        let time = Local::now();
+        writer.write(&first_server(time)).unwrap();
+        writer
+            .write(&first_server(time + chrono::Duration::seconds(30)))
+            .unwrap();
+        assert!(rotated(&path).is_empty());
+
+        writer
+            .write(&first_server(time + chrono::Duration::seconds(90)))
+            .unwrap();
+        writer.close();
+        let files = rotated(&path);
+        assert_eq!(files.len(), 1);
+        assert!(files[0].to_string_lossy().ends_with(".jsonl"));
+        assert_eq!(lines(&files[0]).len(), 2);
+        assert_eq!(lines(&path).len(), 1);
+    }
+
+    #[test]
+    fn parses_sizes_and_durations() {
+        assert_eq!(parse_size("500000"), Ok(500000));
+        assert_eq!(parse_size("64K"), Ok(64 << 10));
+        assert_eq!(parse_size("10m"), Ok(10 << 20));
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("10X").is_err());
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(86400)));
+        assert!(parse_duration("1").is_err());
+    }
+}
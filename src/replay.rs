@@ -0,0 +1,537 @@
+//! Playback of sessions recorded with `--record`.
+//!
+//! The recorded raw registers are decoded again by one offline [`Scanner`]
+//! per server, so the table, history, alarms and event log work as in a
+//! live session. Seeking backwards starts over from the first scan.
+
+use crate::config::Config;
+use crate::datapoint::Datapoint;
+use crate::events::Event;
+use crate::scanner::Scanner;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, NaiveDateTime, NaiveTime, TimeZone};
+use log::warn;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+/// Playback speeds cycled through
+pub const SPEEDS: [u32; 3] = [1, 10, 100];
+
+/// Recorded state of one datapoint.
+struct Sample {
+    index: usize,
+    raw: Vec<u16>,
+    error: Option<String>,
+}
+
+/// One recorded scan of a server.
+struct ScanFrame {
+    timestamp: DateTime<Local>,
+    server: usize,
+    samples: Vec<Sample>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReplayStatus {
+    pub clock: DateTime<Local>,
+    pub start: DateTime<Local>,
+    pub end: DateTime<Local>,
+    pub speed: u32,
+    pub paused: bool,
+}
+
+pub struct Replay {
+    config: Config,
+    scanners: Vec<Scanner>,
+    frames: Vec<ScanFrame>,
+    /// Index of the next frame to apply
+    next: usize,
+    clock: DateTime<Local>,
+    speed: usize,
+    paused: bool,
+    last_tick: Instant,
+    /// Set when the scanners were rebuilt and the UI must drop its events
+    reset: bool,
+}
+
+impl Replay {
+    /// Reads a CSV or JSON Lines recording. Datapoints are matched to the
+    /// configuration by name; unknown ones are skipped.
+    pub fn load<P: AsRef<Path>>(config: &Config, path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read recording {}", path.display()))?;
+
+        // Same column names and order as the recorder
+        let mut columns = HashMap::new();
+        let names = config.column_names();
+        let mut names = names.into_iter();
+        for (server, server_config) in config.servers.iter().enumerate() {
+            let count = config.datapoints_for(&server_config.name).len();
+            for index in 0..count {
+                if let Some(name) = names.next() {
+                    columns.insert(name, (server, index));
+                }
+            }
+        }
+
+        let mut frames = if content.trim_start().starts_with('{') {
+            parse_jsonl(&content, &columns)?
+        } else {
+            parse_csv(&content, &columns)?
+        };
+        if frames.is_empty() {
+            anyhow::bail!("No recorded scans found in {}", path.display());
+        }
+        frames.sort_by_key(|frame| frame.timestamp);
+
+        // Replayed datapoints must never be written to a device
+        let mut config = config.clone();
+        for dp in config.datapoints.iter_mut() {
+            dp.writable = false;
+        }
+
+        let clock = frames[0].timestamp;
+        Ok(Self {
+            scanners: new_scanners(&config),
+            config,
+            frames,
+            next: 0,
+            clock,
+            speed: 0,
+            paused: false,
+            last_tick: Instant::now(),
+            reset: false,
+        })
+    }
+
+    /// Advances the replay clock by the time since the last tick and applies
+    /// the scans recorded up to it. Pauses at the end of the recording.
+    pub fn tick(&mut self) {
+        let elapsed = self.last_tick.elapsed();
+        self.last_tick = Instant::now();
+        if self.paused {
+            return;
+        }
+        let step = elapsed * SPEEDS[self.speed];
+        self.clock += chrono::Duration::from_std(step).unwrap_or_default();
+        self.apply_until_clock();
+        if self.next >= self.frames.len() {
+            self.clock = self.end();
+            self.paused = true;
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        // Play from the start again once the end was reached
+        if self.paused && self.next >= self.frames.len() {
+            self.seek(self.start());
+        }
+        self.paused = !self.paused;
+    }
+
+    pub fn next_speed(&mut self) {
+        self.speed = (self.speed + 1) % SPEEDS.len();
+    }
+
+    /// Jumps forward or back by ten seconds of recording per unit of speed.
+    pub fn skip(&mut self, forward: bool) {
+        let step = chrono::Duration::seconds(10 * SPEEDS[self.speed] as i64);
+        let target = if forward {
+            self.clock + step
+        } else {
+            self.clock - step
+        };
+        self.seek(target);
+    }
+
+    /// Moves the replay clock to `target`, clamped to the recording.
+    pub fn seek(&mut self, target: DateTime<Local>) {
+        let target = target.clamp(self.start(), self.end());
+        if target < self.clock {
+            self.scanners = new_scanners(&self.config);
+            self.next = 0;
+            self.reset = true;
+        }
+        self.clock = target;
+        self.apply_until_clock();
+    }
+
+    /// Parses `HH:MM[:SS]` on the current replay day or a full
+    /// `YYYY-MM-DD HH:MM[:SS]`.
+    pub fn parse_time(&self, input: &str) -> Result<DateTime<Local>> {
+        let input = input.trim();
+        let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+            .iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(input, format).ok())
+            .or_else(|| {
+                ["%H:%M:%S", "%H:%M"]
+                    .iter()
+                    .find_map(|format| NaiveTime::parse_from_str(input, format).ok())
+                    .map(|time| self.clock.date_naive().and_time(time))
+            })
+            .with_context(|| format!("'{}' is not a time like 14:05:00", input))?;
+        Local
+            .from_local_datetime(&naive)
+            .earliest()
+            .with_context(|| format!("'{}' does not exist in the local time zone", input))
+    }
+
+    /// True once after the scanners were rebuilt by a backwards seek.
+    pub fn take_reset(&mut self) -> bool {
+        std::mem::take(&mut self.reset)
+    }
+
+    pub fn datapoints(&self, server: usize) -> &[Datapoint] {
+        self.scanners[server].get_datapoints()
+    }
+
+    pub fn take_events(&mut self, server: usize) -> Vec<Event> {
+        self.scanners[server].take_events()
+    }
+
+    pub fn status(&self) -> ReplayStatus {
+        ReplayStatus {
+            clock: self.clock,
+            start: self.start(),
+            end: self.end(),
+            speed: SPEEDS[self.speed],
+            paused: self.paused,
+        }
+    }
+
+    fn start(&self) -> DateTime<Local> {
+        self.frames[0].timestamp
+    }
+
+    fn end(&self) -> DateTime<Local> {
+        self.frames[self.frames.len() - 1].timestamp
+    }
+
+    fn apply_until_clock(&mut self) {
+        while let Some(frame) = self.frames.get(self.next)
+            && frame.timestamp <= self.clock
+        {
+            let scanner = &mut self.scanners[frame.server];
+            for sample in &frame.samples {
+                match sample.error {
+                    Some(ref error) => {
+                        scanner.replay_error(sample.index, error.clone(), frame.timestamp)
+                    }
+                    None => scanner.replay_registers(sample.index, &sample.raw, frame.timestamp),
+                }
+            }
+            self.next += 1;
+        }
+    }
+}
+
+fn new_scanners(config: &Config) -> Vec<Scanner> {
+    config
+        .servers
+        .iter()
+        .map(|server| {
+            Scanner::new(
+                server.clone(),
+                config.datapoints_for(&server.name),
+                config.history_size,
+            )
+        })
+        .collect()
+}
+
+fn parse_timestamp(text: &str) -> Result<DateTime<Local>> {
+    Ok(DateTime::parse_from_rfc3339(text)
+        .with_context(|| format!("Invalid timestamp '{}'", text))?
+        .with_timezone(&Local))
+}
+
+fn parse_jsonl(content: &str, columns: &HashMap<String, (usize, usize)>) -> Result<Vec<ScanFrame>> {
+    let mut frames = Vec::new();
+    let mut unknown = 0;
+    for (number, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Value = serde_json::from_str(line)
+            .with_context(|| format!("Line {}: invalid JSON", number + 1))?;
+        let timestamp = parse_timestamp(record["timestamp"].as_str().unwrap_or_default())
+            .with_context(|| format!("Line {}", number + 1))?;
+
+        let mut frame: Option<ScanFrame> = None;
+        let datapoints = record["datapoints"].as_object().into_iter().flatten();
+        for (name, recorded) in datapoints {
+            let Some(&(server, index)) = columns.get(name) else {
+                unknown += 1;
+                continue;
+            };
+            let raw = recorded["raw"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|w| w.as_u64().map(|w| w as u16))
+                .collect();
+            let error = recorded["error"].as_str().map(str::to_string);
+            frame
+                .get_or_insert_with(|| ScanFrame {
+                    timestamp,
+                    server,
+                    samples: Vec::new(),
+                })
+                .samples
+                .push(Sample { index, raw, error });
+        }
+        frames.extend(frame);
+    }
+    if unknown > 0 {
+        warn!("Skipped {} recorded values of unknown datapoints", unknown);
+    }
+    Ok(frames)
+}
+
+fn parse_csv(content: &str, columns: &HashMap<String, (usize, usize)>) -> Result<Vec<ScanFrame>> {
+    let mut rows = csv_rows(content).into_iter();
+    let header = rows.next().context("Empty recording")?;
+    if header.first().map(String::as_str) != Some("timestamp") {
+        anyhow::bail!("Not a recording: the first column must be 'timestamp'");
+    }
+    // Every datapoint has a value, raw and error column
+    let datapoints: Vec<Option<(usize, usize)>> = header
+        .iter()
+        .skip(2)
+        .step_by(3)
+        .map(|name| columns.get(name).copied())
+        .collect();
+    let unknown = datapoints.iter().filter(|dp| dp.is_none()).count();
+    if unknown > 0 {
+        warn!(
+            "Skipping {} recorded columns of unknown datapoints",
+            unknown
+        );
+    }
+
+    let mut frames = Vec::new();
+    for (number, row) in rows.enumerate() {
+        let Some(timestamp) = row.first().filter(|t| !t.is_empty()) else {
+            continue;
+        };
+        let timestamp =
+            parse_timestamp(timestamp).with_context(|| format!("Row {}", number + 2))?;
+
+        let mut frame: Option<ScanFrame> = None;
+        for (k, dp) in datapoints.iter().enumerate() {
+            let Some(&(server, index)) = dp.as_ref() else {
+                continue;
+            };
+            let cell = |offset: usize| {
+                row.get(2 + k * 3 + offset)
+                    .map(String::as_str)
+                    .unwrap_or("")
+            };
+            let (raw, error) = (cell(1), cell(2));
+            // Columns of other servers and datapoints without data are empty
+            if raw.is_empty() && error.is_empty() {
+                continue;
+            }
+            let raw = raw
+                .split_whitespace()
+                .map(|w| u16::from_str_radix(w, 16))
+                .collect::<Result<Vec<u16>, _>>()
+                .with_context(|| format!("Row {}: invalid raw registers '{}'", number + 2, raw))?;
+            let error = (!error.is_empty()).then(|| error.to_string());
+            frame
+                .get_or_insert_with(|| ScanFrame {
+                    timestamp,
+                    server,
+                    samples: Vec::new(),
+                })
+                .samples
+                .push(Sample { index, raw, error });
+        }
+        frames.extend(frame);
+    }
+    Ok(frames)
+}
+
+/// Splits CSV text into rows of fields, honouring quoted fields.
+fn csv_rows(content: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = content.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => row.push(std::mem::take(&mut field)),
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            '\r' if !quoted => {}
+            _ => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows
+}
+
+/// Duration between two replay times for display, e.g. `01:02:03`.
+pub fn format_offset(offset: chrono::Duration) -> String {
+    let seconds = offset.num_seconds().max(0);
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recorder::{RecordFormat, RecordOptions, RecordWriter, ScanRecord};
+    use serde_json::json;
+
+    fn config() -> Config {
+        Config::from_json(json!({
+            "servers": [
+                { "name": "plc", "protocol": "modbus", "host": "127.0.0.1", "port": 502 },
+                { "name": "drive", "protocol": "modbus", "host": "127.0.0.1", "port": 503 },
+            ],
+            "datapoints": [
+                { "name": "temp", "server": "plc", "address": 0, "length": 1, "scale": 0.1 },
+                { "name": "mode, \"auto\"", "server": "plc", "address": 1, "length": 4,
+                  "data_type": "string" },
+                { "name": "speed", "server": "drive", "address": 0, "length": 1,
+                  "data_type": "i16" },
+            ],
+        }))
+        .unwrap()
+    }
+
+    /// Records two scans of both servers the way the scan loops do and
+    /// returns the scanners holding the last state.
+    fn record(config: &Config, path: &Path, format: RecordFormat) -> Vec<Scanner> {
+        let options = RecordOptions {
+            path: path.to_path_buf(),
+            format,
+            max_size: None,
+            max_age: None,
+        };
+        let mut writer = RecordWriter::new(options, config.column_names());
+        let mut scanners = new_scanners(config);
+        let start = Local::now();
+        let text = [0x6F6E, 0x2C20, 0x2278, 0x2200];
+        for (second, temp) in [215, 220].into_iter().enumerate() {
+            let time = start + chrono::Duration::seconds(second as i64);
+            scanners[0].replay_registers(0, &[temp], time);
+            scanners[0].replay_registers(1, &text, time);
+            if second == 0 {
+                scanners[1].replay_error(0, "timeout".to_string(), time);
+            } else {
+                scanners[1].replay_registers(0, &[0xFFFE], time);
+            }
+            let mut first_column = 0;
+            for (scanner, server) in scanners.iter().zip(&config.servers) {
+                let datapoints = scanner.get_datapoints();
+                let record = ScanRecord::new(time, &server.name, first_column, datapoints);
+                writer.write(&record).unwrap();
+                first_column += datapoints.len();
+            }
+        }
+        writer.close();
+        scanners
+    }
+
+    /// Value or error of every datapoint of every server.
+    fn state(datapoints: &[&[Datapoint]]) -> Vec<(Option<String>, Option<String>)> {
+        datapoints
+            .iter()
+            .flat_map(|dps| dps.iter())
+            .map(|dp| (dp.display_value(), dp.error.clone()))
+            .collect()
+    }
+
+    fn round_trip(format: RecordFormat) {
+        let dir = std::env::temp_dir().join(format!(
+            "datapoint_tui_{}_replay_{}",
+            std::process::id(),
+            format.extension()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("scans.{}", format.extension()));
+        let config = config();
+        let recorded = record(&config, &path, format);
+
+        let mut replay = Replay::load(&config, &path).unwrap();
+        let status = replay.status();
+        assert_eq!((status.end - status.start).num_seconds(), 1);
+
+        replay.seek(status.end);
+        let expected: Vec<&[Datapoint]> = recorded.iter().map(|s| s.get_datapoints()).collect();
+        let replayed = state(&[replay.datapoints(0), replay.datapoints(1)]);
+        assert_eq!(replayed, state(&expected));
+        assert_eq!(replayed[1].0.as_deref(), Some("on, \"x\""));
+        assert_eq!(replayed[2].0.as_deref(), Some("-2"));
+        assert!(replay.datapoints(0).iter().all(|dp| !dp.writable));
+
+        // Seeking back replays from the start
+        replay.seek(status.start);
+        assert!(replay.take_reset());
+        assert_eq!(
+            replay.datapoints(0)[0].display_value().as_deref(),
+            Some("21.5")
+        );
+        assert_eq!(replay.datapoints(1)[0].error.as_deref(), Some("timeout"));
+    }
+
+    #[test]
+    fn replays_a_csv_recording() {
+        round_trip(RecordFormat::Csv);
+    }
+
+    #[test]
+    fn replays_a_json_lines_recording() {
+        round_trip(RecordFormat::Jsonl);
+    }
+
+    #[test]
+    fn splits_quoted_csv_fields() {
+        let content = "a,\"b, c\",\"say \"\"hi\"\"\"\r\n\"two\nlines\",\n";
+        assert_eq!(
+            csv_rows(content),
+            [vec!["a", "b, c", "say \"hi\""], vec!["two\nlines", ""],]
+        );
+        assert_eq!(csv_rows("x,y"), [vec!["x", "y"]]);
+        assert!(csv_rows("").is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_recordings() {
+        let columns = HashMap::new();
+        let error = parse_jsonl("{\"timestamp\": \"2024-01-01T00:00:00Z\"}\n{", &columns)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("Line 2"), "{:#}", error);
+        assert!(parse_csv("time,server\n", &columns).is_err());
+
+        let columns = HashMap::from([("temp".to_string(), (0, 0))]);
+        let content = "timestamp,server,temp,temp raw,temp error\n\
+                       2024-01-01T00:00:00Z,plc,1,XYZ,\n";
+        let error = parse_csv(content, &columns).err().unwrap();
+        assert!(format!("{:#}", error).contains("Row 2"), "{:#}", error);
+    }
+}
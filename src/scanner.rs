@@ -1,214 +1,890 @@
-use crate::config::{Config, DataType, Endianness};
+use crate::alarm::Alarm;
+use crate::codec::{
+    bitfield32_bit_location, decode_bits, decode_value, encode_string, encode_value,
+};
+use crate::config::{DatapointConfig, Parity, RegisterType, ServerConfig, Transport};
 use crate::datapoint::{DataValue, Datapoint};
+use crate::events::{Event, EventKind};
+use crate::history::History;
+use crate::read_plan::{ReadBlock, plan_reads};
 use anyhow::Result;
+use chrono::{DateTime, Local};
 use log::{debug, error, info, warn};
-use tokio::time::Duration;
+use std::collections::BTreeMap;
+use tokio::time::{Duration, Instant};
+use tokio_modbus::client::Context;
 use tokio_modbus::prelude::*;
+use tokio_serial::{DataBits, SerialStream, StopBits};
+
+/// Error of a read request that took too long; the connection is dropped
+const READ_TIMEOUT: &str = "Read timeout";
+
+/// State of the long-lived Modbus client connection owned by the [`Scanner`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConnectionState {
+    /// No connection attempt has been made yet
+    Idle,
+    /// A connection attempt is in progress
+    Connecting,
+    Connected,
+    /// The last connection attempt failed; the next one is made at `until`
+    Backoff {
+        until: Instant,
+        attempt: u32,
+    },
+}
+
+/// Scanner health counters, exported as metrics.
+#[derive(Debug, Clone, Default)]
+pub struct ScanStats {
+    pub scans: u64,
+    /// Time the last scan took, including connecting
+    pub last_scan: Duration,
+    /// Failed read requests by kind, e.g. "exception" or "timeout"
+    pub read_errors: BTreeMap<&'static str, u64>,
+    /// Successful connections after a lost or failed one
+    pub reconnects: u64,
+}
 
 pub struct Scanner {
-    config: Config,
+    server: ServerConfig,
+    datapoint_configs: Vec<DatapointConfig>,
     datapoints: Vec<Datapoint>,
+    ctx: Option<Context>,
+    connection: ConnectionState,
+    failed_attempts: u32,
+    plan: Vec<ReadBlock>,
+    /// Events not yet collected by the UI
+    events: Vec<Event>,
+    stats: ScanStats,
 }
 
 impl Scanner {
-    pub fn new(config: Config) -> Self {
-        let datapoints = config
-            .datapoints
+    /// Creates a scanner for one server and the datapoints assigned to it,
+    /// keeping up to `history_size` samples per datapoint.
+    pub fn new(
+        server: ServerConfig,
+        datapoint_configs: Vec<DatapointConfig>,
+        history_size: usize,
+    ) -> Self {
+        let datapoints = datapoint_configs
             .iter()
-            .map(|dp| {
-                if let Some(ref bitfields) = dp.bitfields {
-                    let mut bitfield_map = std::collections::HashMap::new();
-                    for bf in bitfields {
-                        bitfield_map.insert(bf.bit, bf.name.clone());
-                    }
-                    Datapoint::with_bitfields(
-                        dp.name.clone(),
-                        dp.address,
-                        dp.description.clone(),
-                        bitfield_map,
-                    )
-                } else {
-                    Datapoint::new(dp.name.clone(), dp.address, dp.description.clone())
-                }
-            })
+            .map(|dp| new_datapoint(&server, dp, history_size))
             .collect();
 
-        Self { config, datapoints }
+        let plan = plan_reads(
+            &datapoint_configs,
+            server.unit_id,
+            server.max_read_gap,
+            server.max_read_block,
+        );
+        info!("Planned {} block reads for {}", plan.len(), server.name);
+
+        Self {
+            server,
+            datapoint_configs,
+            datapoints,
+            plan,
+            ctx: None,
+            connection: ConnectionState::Idle,
+            failed_attempts: 0,
+            events: Vec::new(),
+            stats: ScanStats::default(),
+        }
     }
 
     pub async fn scan_once(&mut self) -> Result<()> {
-        if self.config.server.protocol.to_lowercase() == "modbus" {
-            self.scan_modbus().await?;
-        } else {
-            anyhow::bail!("Unsupported protocol: {}", self.config.server.protocol);
+        if self.server.protocol.to_lowercase() != "modbus" {
+            anyhow::bail!("Unsupported protocol: {}", self.server.protocol);
         }
-        Ok(())
+        let started = Instant::now();
+        let result = self.scan_modbus().await;
+        self.stats.scans += 1;
+        self.stats.last_scan = started.elapsed();
+        result
     }
 
-    async fn scan_modbus(&mut self) -> Result<()> {
-        // Handle IPv6 addresses by wrapping them in brackets
-        let socket_addr = if self.config.server.host.contains(':') {
-            // IPv6 address - needs brackets
-            format!("[{}]:{}", self.config.server.host, self.config.server.port)
-        } else {
-            // IPv4 address or hostname
-            format!("{}:{}", self.config.server.host, self.config.server.port)
-        };
+    async fn connect(&mut self) -> Result<()> {
+        self.connection = ConnectionState::Connecting;
+        let server = &self.server;
+        let slave = Slave(server.unit_id);
 
-        info!("Connecting to Modbus server at {}", socket_addr);
+        let connect_result = match server.transport {
+            Transport::Tcp => {
+                info!("Connecting to Modbus server at {}", server.describe());
 
-        // Add timeout to connection attempt
-        let connect_result = tokio::time::timeout(
-            Duration::from_secs(5),
-            tcp::connect_slave(socket_addr.parse()?, Slave(self.config.server.unit_id)),
-        )
-        .await;
+                // Resolving a hostname counts against the timeout too
+                let host = server.host.as_str();
+                let connect = async {
+                    let socket_addr = tokio::net::lookup_host((host, server.port))
+                        .await?
+                        .next()
+                        .ok_or_else(|| {
+                            std::io::Error::new(
+                                std::io::ErrorKind::NotFound,
+                                format!("No address for {}", host),
+                            )
+                        })?;
+                    debug!("Resolved {} to {}", host, socket_addr);
+                    tcp::connect_slave(socket_addr, slave).await
+                };
+                tokio::time::timeout(Duration::from_secs(5), connect).await
+            }
+            Transport::Rtu => {
+                info!("Opening serial port {}", server.describe());
+                Ok(open_serial(server).map(|stream| rtu::attach_slave(stream, slave)))
+            }
+        };
 
-        let mut ctx = match connect_result {
+        match connect_result {
             Ok(Ok(ctx)) => {
                 debug!("Connected successfully");
-                ctx
+                if self.failed_attempts > 0 {
+                    self.stats.reconnects += 1;
+                    self.log_event(
+                        EventKind::ConnectionRestored,
+                        None,
+                        format!("Connected to {}", self.server.describe()),
+                    );
+                }
+                self.ctx = Some(ctx);
+                self.connection = ConnectionState::Connected;
+                self.failed_attempts = 0;
+                Ok(())
             }
             Ok(Err(e)) => {
                 error!("Connection failed: {}", e);
-                for i in 0..self.datapoints.len() {
-                    self.datapoints[i].update_error(format!("Connection failed: {}", e));
-                }
+                self.mark_disconnected(format!("Connection failed: {}", e));
                 anyhow::bail!("Failed to connect to Modbus server: {}", e);
             }
             Err(_) => {
                 error!("Connection timeout");
-                for i in 0..self.datapoints.len() {
-                    self.datapoints[i].update_error("Connection timeout".to_string());
-                }
+                self.mark_disconnected("Connection timeout".to_string());
                 anyhow::bail!("Connection timeout");
             }
+        }
+    }
+
+    /// Drops the client context, flags every datapoint with `reason` and
+    /// schedules the next reconnect attempt with exponential backoff.
+    fn mark_disconnected(&mut self, reason: String) {
+        self.ctx = None;
+        if self.failed_attempts == 0 {
+            self.log_event(EventKind::ConnectionLost, None, reason.clone());
+        }
+        for dp in self.datapoints.iter_mut() {
+            dp.update_error(reason.clone());
+        }
+
+        self.failed_attempts = self.failed_attempts.saturating_add(1);
+        let initial = self.server.reconnect_delay_ms;
+        let max = self.server.max_reconnect_delay_ms.max(initial);
+        let delay = initial
+            .saturating_mul(1u64 << (self.failed_attempts - 1).min(16))
+            .min(max);
+        info!(
+            "Next reconnect attempt in {}ms (attempt {})",
+            delay, self.failed_attempts
+        );
+        self.connection = ConnectionState::Backoff {
+            until: Instant::now() + Duration::from_millis(delay),
+            attempt: self.failed_attempts,
+        };
+    }
+
+    async fn scan_modbus(&mut self) -> Result<()> {
+        if self.ctx.is_none() {
+            if let ConnectionState::Backoff { until, .. } = self.connection
+                && Instant::now() < until
+            {
+                debug!("Skipping scan, reconnect backoff still active");
+                return Ok(());
+            }
+            self.connect().await?;
+        }
+        let Some(ctx) = self.ctx.as_mut() else {
+            return Ok(());
         };
 
-        let endianness = self.config.server.endianness;
+        let frame_delay = self.server.inter_frame_delay();
 
-        for (i, dp_config) in self.config.datapoints.iter().enumerate() {
+        let mut block_index = 0;
+        let mut first_request = true;
+        while block_index < self.plan.len() {
+            if !first_request {
+                frame_gap(frame_delay).await;
+            }
+            first_request = false;
+
+            let block = &self.plan[block_index];
             debug!(
-                "Reading {} at address {} (length {})",
-                dp_config.name, dp_config.address, dp_config.length
+                "Reading block at address {} (length {}) from unit {} for {} datapoints",
+                block.address,
+                block.count,
+                block.unit_id,
+                block.members.len()
             );
 
-            // Add timeout to read operations as well
-            let read_result = tokio::time::timeout(
-                Duration::from_secs(2),
-                match dp_config.register_type {
-                    crate::config::RegisterType::Holding => {
-                        ctx.read_holding_registers(dp_config.address, dp_config.length)
-                    }
-                    crate::config::RegisterType::Input => {
-                        ctx.read_input_registers(dp_config.address, dp_config.length)
-                    }
-                },
-            )
-            .await;
-
-            match read_result {
-                Ok(Ok(Ok(registers))) => {
+            ctx.set_slave(Slave(block.unit_id));
+            let result = read_registers(ctx, block.register_type, block.address, block.count).await;
+            if let Err(ref e) = result {
+                *self.stats.read_errors.entry(e.kind()).or_default() += 1;
+            }
+            match result {
+                Ok(registers) => {
                     debug!(
                         "Successfully read {} registers: {:?}",
                         registers.len(),
                         registers
                     );
-                    let value = match dp_config.data_type {
-                        DataType::U16 => {
-                            if !registers.is_empty() {
-                                DataValue::U16(registers[0])
-                            } else {
-                                continue;
-                            }
-                        }
-                        DataType::I16 => {
-                            if !registers.is_empty() {
-                                DataValue::I16(registers[0] as i16)
-                            } else {
-                                continue;
-                            }
-                        }
-                        DataType::U32 => {
-                            if registers.len() >= 2 {
-                                let val = match endianness {
-                                    Endianness::Big => {
-                                        ((registers[0] as u32) << 16) | (registers[1] as u32)
-                                    }
-                                    Endianness::Little => {
-                                        ((registers[1] as u32) << 16) | (registers[0] as u32)
-                                    }
-                                };
-                                DataValue::U32(val)
-                            } else {
-                                continue;
-                            }
-                        }
-                        DataType::I32 => {
-                            if registers.len() >= 2 {
-                                let val = match endianness {
-                                    Endianness::Big => {
-                                        ((registers[0] as u32) << 16) | (registers[1] as u32)
-                                    }
-                                    Endianness::Little => {
-                                        ((registers[1] as u32) << 16) | (registers[0] as u32)
-                                    }
-                                };
-                                DataValue::I32(val as i32)
-                            } else {
-                                continue;
-                            }
-                        }
-                        DataType::F32 => {
-                            if registers.len() >= 2 {
-                                let bytes = match endianness {
-                                    Endianness::Big => [
-                                        (registers[0] >> 8) as u8,
-                                        (registers[0] & 0xFF) as u8,
-                                        (registers[1] >> 8) as u8,
-                                        (registers[1] & 0xFF) as u8,
-                                    ],
-                                    Endianness::Little => [
-                                        (registers[1] >> 8) as u8,
-                                        (registers[1] & 0xFF) as u8,
-                                        (registers[0] >> 8) as u8,
-                                        (registers[0] & 0xFF) as u8,
-                                    ],
-                                };
-                                DataValue::F32(f32::from_be_bytes(bytes))
-                            } else {
-                                continue;
-                            }
-                        }
-                        DataType::Bitfield => {
-                            if !registers.is_empty() {
-                                DataValue::Bitfield(registers[0])
-                            } else {
-                                continue;
-                            }
+                    let now = Local::now();
+                    for &i in &block.members {
+                        let dp_config = &self.datapoint_configs[i];
+                        let words = block.slice(dp_config, &registers);
+                        let events = store_registers(
+                            &mut self.datapoints[i],
+                            dp_config,
+                            &self.server,
+                            words,
+                            now,
+                        );
+                        self.events.extend(events);
+                    }
+                }
+                Err(ReadError::Exception(Exception::IllegalDataAddress))
+                    if block.members.len() > 1 =>
+                {
+                    // One unmapped address must not blank the whole block, so
+                    // replace it with per-datapoint reads for this and later scans.
+                    warn!(
+                        "Block read at address {} (length {}) rejected, falling back to per-datapoint reads",
+                        block.address, block.count
+                    );
+                    let singles = block.split(&self.datapoint_configs);
+                    self.plan.splice(block_index..=block_index, singles);
+                    continue;
+                }
+                Err(ReadError::Exception(e)) => {
+                    for &i in &block.members {
+                        warn!(
+                            "Modbus exception for {}: {}",
+                            self.datapoint_configs[i].name, e
+                        );
+                        let error = format!("Modbus exception: {}", e);
+                        // Log when the exception starts, not on every scan
+                        if self.datapoints[i].error.as_ref() != Some(&error) {
+                            self.events.push(Event::new(
+                                EventKind::ModbusException,
+                                &self.server.name,
+                                Some(&self.datapoints[i].name),
+                                format!("Read: {}", e),
+                            ));
                         }
-                    };
-                    self.datapoints[i].update_value(value);
+                        self.datapoints[i].update_error(error);
+                    }
                 }
-                Ok(Ok(Err(e))) => {
-                    warn!("Modbus exception for {}: {}", dp_config.name, e);
-                    self.datapoints[i].update_error(format!("Modbus exception: {}", e));
+                Err(ReadError::Failed(e)) => {
+                    for &i in &block.members {
+                        warn!("Read error for {}: {}", self.datapoint_configs[i].name, e);
+                        self.datapoints[i].update_error(format!("Read error: {}", e));
+                    }
                 }
-                Ok(Err(e)) => {
-                    warn!("Read error for {}: {}", dp_config.name, e);
-                    self.datapoints[i].update_error(format!("Read error: {}", e));
+                Err(ReadError::ConnectionLost(reason)) => {
+                    warn!("{} while reading address {}", reason, block.address);
+                    self.mark_disconnected(reason);
+                    return Ok(());
                 }
-                Err(_) => {
-                    warn!("Read timeout for {}", dp_config.name);
-                    self.datapoints[i].update_error("Read timeout".to_string());
+            }
+
+            block_index += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Writes `value` to the holding registers of datapoint `index` with
+    /// FC6 (single register) or FC16 (multiple registers), or to its coils
+    /// with FC5 (single coil) or FC15 (multiple coils).
+    pub async fn write_datapoint(&mut self, index: usize, value: DataValue) -> Result<()> {
+        self.check_writable(index)?;
+        let description = self.datapoints[index].format_value(&value);
+        let address = self.datapoint_configs[index].address;
+        let is_coil = self.datapoint_configs[index].register_type == RegisterType::Coil;
+        let frame_delay = self.server.inter_frame_delay();
+        let dp_config = &self.datapoint_configs[index];
+        let order = dp_config.byte_order(&self.server);
+        let registers = match value {
+            DataValue::String(ref text) => encode_string(text, dp_config.length, order),
+            _ => encode_value(&value, order),
+        };
+        info!(
+            "Writing {} to {} at address {}: {:?}",
+            value, self.datapoint_configs[index].name, address, registers
+        );
+
+        let unit_id = self.datapoints[index].unit_id;
+        let ctx = match self.context().await {
+            Ok(ctx) => ctx,
+            Err(e) => return self.finish_write(index, description, Err(e.to_string())),
+        };
+        ctx.set_slave(Slave(unit_id));
+        let write_result = tokio::time::timeout(Duration::from_secs(2), async {
+            frame_gap(frame_delay).await;
+            if is_coil {
+                let coils: Vec<bool> = registers.iter().map(|&w| w != 0).collect();
+                if let [coil] = coils[..] {
+                    ctx.write_single_coil(address, coil).await
+                } else {
+                    ctx.write_multiple_coils(address, &coils).await
                 }
+            } else if let [word] = registers[..] {
+                ctx.write_single_register(address, word).await
+            } else {
+                ctx.write_multiple_registers(address, &registers).await
             }
+        })
+        .await;
+
+        let result = self.check_write_result(write_result);
+        self.finish_write(index, description, result)
+    }
+
+    /// Sets or clears a single bit of a bitfield datapoint, either with FC22
+    /// Mask Write Register or with a read-modify-write (FC3 + FC6). For coil
+    /// datapoints the coil at `address + bit` is written with FC5.
+    pub async fn write_bit(&mut self, index: usize, bit: u8, value: bool) -> Result<()> {
+        self.check_writable(index)?;
+        let dp = &self.datapoints[index];
+        if dp.read_only_bits.contains(&bit) {
+            anyhow::bail!("Bit {} of {} is read-only", bit, dp.name);
         }
+        let description = format!("bit {} = {}", bit, value as u8);
+        let dp_config = &self.datapoint_configs[index];
+        let is_coil = dp_config.register_type == RegisterType::Coil;
+        // Bits of 32-bit bitfields live in either register, depending on the order
+        let (address, bit) = if !is_coil && dp_config.length >= 2 {
+            let (offset, bit) = bitfield32_bit_location(bit, dp_config.byte_order(&self.server));
+            (dp_config.address + offset, bit)
+        } else {
+            (dp_config.address, bit)
+        };
+        let mask_write = self.server.mask_write;
+        let frame_delay = self.server.inter_frame_delay();
+        info!(
+            "Writing {} of {} at address {} ({})",
+            description,
+            dp.name,
+            address,
+            if is_coil {
+                "FC5"
+            } else if mask_write {
+                "FC22"
+            } else {
+                "read-modify-write"
+            }
+        );
+
+        let unit_id = self.datapoints[index].unit_id;
+        let ctx = match self.context().await {
+            Ok(ctx) => ctx,
+            Err(e) => return self.finish_write(index, description, Err(e.to_string())),
+        };
+        ctx.set_slave(Slave(unit_id));
+        let write_result = tokio::time::timeout(Duration::from_secs(2), async {
+            frame_gap(frame_delay).await;
+            if is_coil {
+                ctx.write_single_coil(address + bit as u16, value).await
+            } else if mask_write {
+                let and_mask = !(1u16 << bit);
+                let or_mask = (value as u16) << bit;
+                ctx.masked_write_register(address, and_mask, or_mask).await
+            } else {
+                read_modify_write_bit(ctx, address, bit, value, frame_delay).await
+            }
+        })
+        .await;
+
+        let result = self.check_write_result(write_result);
+        self.finish_write(index, description, result)
+    }
 
+    fn check_writable(&self, index: usize) -> Result<()> {
+        let Some(dp) = self.datapoints.get(index) else {
+            anyhow::bail!("Unknown datapoint index {}", index);
+        };
+        if !dp.writable {
+            anyhow::bail!("Datapoint {} is not writable", dp.name);
+        }
         Ok(())
     }
 
+    /// Returns the client context, connecting first if necessary.
+    async fn context(&mut self) -> Result<&mut Context> {
+        if self.ctx.is_none() {
+            self.connect().await?;
+        }
+        self.ctx
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("Not connected"))
+    }
+
+    fn check_write_result(
+        &mut self,
+        write_result: Result<tokio_modbus::Result<()>, tokio::time::error::Elapsed>,
+    ) -> Result<(), String> {
+        match write_result {
+            Ok(Ok(Ok(()))) => Ok(()),
+            Ok(Ok(Err(e))) => Err(format!("Modbus exception: {}", e)),
+            Ok(Err(tokio_modbus::Error::Transport(e))) => {
+                self.mark_disconnected(format!("Connection lost: {}", e));
+                Err(format!("Connection lost: {}", e))
+            }
+            Ok(Err(e)) => Err(format!("Write error: {}", e)),
+            Err(_) => {
+                self.mark_disconnected("Write timeout".to_string());
+                Err("Write timeout".to_string())
+            }
+        }
+    }
+
+    fn finish_write(
+        &mut self,
+        index: usize,
+        description: String,
+        result: Result<(), String>,
+    ) -> Result<()> {
+        match result {
+            Ok(()) => {
+                self.datapoints[index].update_write_status(description, None);
+                Ok(())
+            }
+            Err(e) => {
+                warn!("Write to {} failed: {}", self.datapoints[index].name, e);
+                if let Some(exception) = e.strip_prefix("Modbus exception: ") {
+                    let name = self.datapoints[index].name.clone();
+                    self.log_event(
+                        EventKind::ModbusException,
+                        Some(&name),
+                        format!("Write {}: {}", description, exception),
+                    );
+                }
+                self.datapoints[index].update_write_status(description, Some(e.clone()));
+                anyhow::bail!(e)
+            }
+        }
+    }
+
+    fn log_event(&mut self, kind: EventKind, datapoint: Option<&str>, message: String) {
+        self.events
+            .push(Event::new(kind, &self.server.name, datapoint, message));
+    }
+
+    /// Events since the last call.
+    pub fn take_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Applies registers recorded at `timestamp` as if they had just been read.
+    pub fn replay_registers(&mut self, index: usize, words: &[u16], timestamp: DateTime<Local>) {
+        let events = store_registers(
+            &mut self.datapoints[index],
+            &self.datapoint_configs[index],
+            &self.server,
+            words,
+            timestamp,
+        );
+        self.events.extend(events);
+    }
+
+    pub fn replay_error(&mut self, index: usize, error: String, timestamp: DateTime<Local>) {
+        let dp = &mut self.datapoints[index];
+        dp.update_error(error);
+        dp.last_updated = Some(timestamp);
+    }
+
     pub fn get_datapoints(&self) -> &[Datapoint] {
         &self.datapoints
     }
+
+    pub fn connection_state(&self) -> ConnectionState {
+        self.connection
+    }
+
+    pub fn server(&self) -> &ServerConfig {
+        &self.server
+    }
+
+    pub fn stats(&self) -> &ScanStats {
+        &self.stats
+    }
+}
+
+/// Datapoint state for a configured datapoint of `server`, keeping up to
+/// `history_size` samples.
+pub fn new_datapoint(
+    server: &ServerConfig,
+    dp: &DatapointConfig,
+    history_size: usize,
+) -> Datapoint {
+    let mut datapoint = if let Some(ref bitfields) = dp.bitfields {
+        Datapoint::with_bitfields(
+            dp.name.clone(),
+            dp.address,
+            dp.description.clone(),
+            bitfields.clone(),
+        )
+    } else {
+        Datapoint::new(dp.name.clone(), dp.address, dp.description.clone())
+    };
+    datapoint.data_type = dp.data_type;
+    datapoint.register_type = dp.register_type;
+    datapoint.length = dp.length;
+    datapoint.read_only_bits = dp
+        .bitfields
+        .iter()
+        .flatten()
+        .filter(|bf| bf.read_only)
+        .filter_map(|bf| bf.bit)
+        .collect();
+    datapoint.writable = dp.writable && dp.register_type.is_writable();
+    datapoint.server = server.name.clone();
+    datapoint.unit_id = dp.unit_id.unwrap_or(server.unit_id);
+    datapoint.history = History::new(history_size);
+    datapoint.scale = dp.scale.unwrap_or(1.0);
+    datapoint.offset = dp.offset.unwrap_or(0.0);
+    datapoint.unit = dp.unit.clone();
+    datapoint.decimals = dp.decimals;
+    datapoint.enum_labels = dp.enum_labels.clone();
+    datapoint.alarms = dp
+        .alarms
+        .iter()
+        .map(|rule| Alarm::new(rule.clone(), &dp.enum_labels))
+        .collect();
+    datapoint
+}
+
+/// Decodes the registers of one datapoint and stores the value, or a short
+/// read error if there are too few registers.
+fn store_registers(
+    dp: &mut Datapoint,
+    dp_config: &DatapointConfig,
+    server: &ServerConfig,
+    words: &[u16],
+    now: DateTime<Local>,
+) -> Vec<Event> {
+    let value = if dp_config.register_type.is_bit() {
+        decode_bits(words)
+    } else {
+        decode_value(dp_config.data_type, words, dp_config.byte_order(server))
+    };
+    match value {
+        Some(value) => {
+            dp.registers = words.to_vec();
+            dp.update_value(value, now)
+        }
+        None => {
+            dp.update_error(format!(
+                "Short read: got {} of {} registers",
+                words.len(),
+                dp_config.length
+            ));
+            Vec::new()
+        }
+    }
+}
+
+enum ReadError {
+    Exception(Exception),
+    Failed(String),
+    ConnectionLost(String),
+}
+
+impl ReadError {
+    fn kind(&self) -> &'static str {
+        match self {
+            ReadError::Exception(_) => "exception",
+            ReadError::Failed(_) => "failed",
+            ReadError::ConnectionLost(reason) if reason == READ_TIMEOUT => "timeout",
+            ReadError::ConnectionLost(_) => "connection_lost",
+        }
+    }
+}
+
+/// Waits out the RTU inter-frame silence before the next request.
+async fn frame_gap(delay: Duration) {
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+}
+
+fn open_serial(server: &ServerConfig) -> std::io::Result<SerialStream> {
+    let data_bits = match server.data_bits {
+        5 => DataBits::Five,
+        6 => DataBits::Six,
+        7 => DataBits::Seven,
+        _ => DataBits::Eight,
+    };
+    let parity = match server.parity {
+        Parity::None => tokio_serial::Parity::None,
+        Parity::Even => tokio_serial::Parity::Even,
+        Parity::Odd => tokio_serial::Parity::Odd,
+    };
+    let stop_bits = if server.stop_bits == 2 {
+        StopBits::Two
+    } else {
+        StopBits::One
+    };
+
+    let builder = tokio_serial::new(
+        server.device.as_deref().unwrap_or_default(),
+        server.baud_rate,
+    )
+    .data_bits(data_bits)
+    .parity(parity)
+    .stop_bits(stop_bits);
+    Ok(SerialStream::open(&builder)?)
+}
+
+/// Coils and discrete inputs are passed around as one word (0 or 1) per bit
+/// so they can share the block planning and slicing with registers.
+fn bits_to_words(result: tokio_modbus::Result<Vec<bool>>) -> tokio_modbus::Result<Vec<u16>> {
+    result.map(|bits| bits.map(|bits| bits.into_iter().map(u16::from).collect()))
+}
+
+async fn read_registers(
+    ctx: &mut Context,
+    register_type: RegisterType,
+    address: u16,
+    count: u16,
+) -> Result<Vec<u16>, ReadError> {
+    // Add timeout to read operations as well
+    let read_result = tokio::time::timeout(Duration::from_secs(2), async {
+        match register_type {
+            RegisterType::Holding => ctx.read_holding_registers(address, count).await,
+            RegisterType::Input => ctx.read_input_registers(address, count).await,
+            RegisterType::Coil => bits_to_words(ctx.read_coils(address, count).await),
+            RegisterType::DiscreteInput => {
+                bits_to_words(ctx.read_discrete_inputs(address, count).await)
+            }
+        }
+    })
+    .await;
+
+    match read_result {
+        Ok(Ok(Ok(registers))) => Ok(registers),
+        Ok(Ok(Err(e))) => Err(ReadError::Exception(e)),
+        Ok(Err(tokio_modbus::Error::Transport(e))) => {
+            Err(ReadError::ConnectionLost(format!("Connection lost: {}", e)))
+        }
+        Ok(Err(e)) => Err(ReadError::Failed(e.to_string())),
+        // A late response would be matched against the next request,
+        // so a timed out transaction invalidates the connection.
+        Err(_) => Err(ReadError::ConnectionLost(READ_TIMEOUT.to_string())),
+    }
+}
+
+async fn read_modify_write_bit(
+    ctx: &mut Context,
+    address: u16,
+    bit: u8,
+    value: bool,
+    frame_delay: Duration,
+) -> tokio_modbus::Result<()> {
+    let current = match ctx.read_holding_registers(address, 1).await? {
+        Ok(words) => words.first().copied().unwrap_or_default(),
+        Err(e) => return Ok(Err(e)),
+    };
+    frame_gap(frame_delay).await;
+    let updated = if value {
+        current | (1 << bit)
+    } else {
+        current & !(1 << bit)
+    };
+    ctx.write_single_register(address, updated).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::simulate::Simulator;
+    use serde_json::{Value, json};
+    use tokio::net::TcpListener;
+
+    fn config(host: &str, port: u16, datapoints: Value) -> (ServerConfig, Vec<DatapointConfig>) {
+        let config = Config::from_json(json!({
+            "server": { "protocol": "modbus", "host": host, "port": port },
+            "datapoints": datapoints,
+        }))
+        .unwrap();
+        let server = config.servers[0].clone();
+        let configs = config.datapoints_for(&server.name);
+        (server, configs)
+    }
+
+    /// Serves `datapoints` on a free port of localhost.
+    async fn serve(host: &str, datapoints: Value) -> (ServerConfig, Vec<DatapointConfig>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (server, configs) = config(host, port, datapoints);
+        let simulator = Simulator::new(&server, configs.clone()).unwrap();
+        tokio::spawn(simulator.serve(listener));
+        (server, configs)
+    }
+
+    /// Modbus CRC-16, sent low byte first.
+    fn crc(data: &[u8]) -> u16 {
+        let mut crc = 0xFFFF_u16;
+        for &byte in data {
+            crc ^= byte as u16;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0xA001
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+        crc
+    }
+
+    /// RTU slave on the other end of a PTY: answers Read Holding Registers
+    /// for `unit` with each register holding its own address.
+    async fn rtu_slave(mut port: SerialStream, unit: u8) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let mut request = [0u8; 8];
+        while port.read_exact(&mut request).await.is_ok() {
+            assert_eq!(request[6..], crc(&request[..6]).to_le_bytes());
+            assert_eq!(request[..2], [unit, 0x03]);
+            let address = u16::from_be_bytes([request[2], request[3]]);
+            let count = u16::from_be_bytes([request[4], request[5]]);
+            let mut response = vec![unit, 0x03, count as u8 * 2];
+            for register in address..address + count {
+                response.extend(register.to_be_bytes());
+            }
+            response.extend(crc(&response).to_le_bytes());
+            port.write_all(&response).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_over_rtu() {
+        use tokio_serial::SerialPort;
+        let (master, slave) = SerialStream::pair().unwrap();
+        // The scanner opens the slave side by name; the pair's handle stays
+        // open so the master does not see a hangup in between
+        let device = slave.name().unwrap();
+        tokio::spawn(rtu_slave(master, 5));
+
+        let config = Config::from_json(json!({
+            "server": { "protocol": "modbus", "transport": "rtu", "device": device,
+                        "baud_rate": 19200, "unit_id": 5 },
+            "datapoints": [
+                { "name": "a", "address": 10, "length": 1 },
+                { "name": "b", "address": 11, "length": 2, "data_type": "u32" },
+            ],
+        }))
+        .unwrap();
+        let server = config.servers[0].clone();
+        let configs = config.datapoints_for(&server.name);
+        let mut scanner = Scanner::new(server, configs, 1);
+        scanner.scan_once().await.unwrap();
+        scanner.scan_once().await.unwrap();
+        assert_eq!(scanner.connection_state(), ConnectionState::Connected);
+        let values: Vec<Option<String>> = scanner
+            .get_datapoints()
+            .iter()
+            .map(Datapoint::display_value)
+            .collect();
+        assert_eq!(
+            values,
+            [Some("10".to_string()), Some(((11 << 16) + 12).to_string())]
+        );
+        drop(slave);
+    }
+
+    #[tokio::test]
+    async fn connects_by_hostname() {
+        let (server, configs) = serve(
+            "localhost",
+            json!([{ "name": "a", "address": 0, "length": 1,
+                     "simulate": { "generator": "constant", "value": 7 } }]),
+        )
+        .await;
+        let mut scanner = Scanner::new(server, configs, 1);
+        scanner.scan_once().await.unwrap();
+        assert_eq!(scanner.connection_state(), ConnectionState::Connected);
+        assert_eq!(
+            scanner.get_datapoints()[0].display_value().as_deref(),
+            Some("7")
+        );
+    }
+
+    #[tokio::test]
+    async fn rejected_block_falls_back_to_single_reads() {
+        let served = json!([
+            { "name": "a", "address": 0, "length": 1,
+              "simulate": { "generator": "constant", "value": 1 } },
+            { "name": "c", "address": 2, "length": 1,
+              "simulate": { "generator": "constant", "value": 3 } },
+        ]);
+        let (server, _) = serve("127.0.0.1", served).await;
+        // Address 5 is not served, so the merged block 0-5 is rejected
+        let (_, configs) = config(
+            "127.0.0.1",
+            server.port,
+            json!([
+                { "name": "a", "address": 0, "length": 1 },
+                { "name": "b", "address": 5, "length": 1 },
+                { "name": "c", "address": 2, "length": 1 },
+            ]),
+        );
+        let server = ServerConfig {
+            max_read_gap: 10,
+            ..server
+        };
+        let mut scanner = Scanner::new(server, configs, 1);
+        assert_eq!(scanner.plan.len(), 1);
+
+        for _ in 0..2 {
+            scanner.scan_once().await.unwrap();
+            let plan: Vec<(u16, Vec<usize>)> = scanner
+                .plan
+                .iter()
+                .map(|b| (b.address, b.members.clone()))
+                .collect();
+            assert_eq!(plan, [(0, vec![0]), (2, vec![2]), (5, vec![1])]);
+            let datapoints = scanner.get_datapoints();
+            assert_eq!(datapoints[0].display_value().as_deref(), Some("1"));
+            assert_eq!(datapoints[2].display_value().as_deref(), Some("3"));
+            assert_eq!(
+                datapoints[1].error.as_deref(),
+                Some("Modbus exception: Illegal data address")
+            );
+        }
+        assert_eq!(scanner.stats().read_errors["exception"], 3);
+    }
+
+    #[tokio::test]
+    async fn failed_connect_backs_off() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        for host in ["localhost", "unresolvable.invalid"] {
+            let (server, configs) = config(
+                host,
+                port,
+                json!([{ "name": "a", "address": 0, "length": 1 }]),
+            );
+            let mut scanner = Scanner::new(server, configs, 1);
+            assert!(scanner.scan_once().await.is_err());
+            assert!(matches!(
+                scanner.connection_state(),
+                ConnectionState::Backoff { attempt: 1, .. }
+            ));
+            let error = scanner.get_datapoints()[0].error.as_deref().unwrap();
+            assert!(error.starts_with("Connection failed"), "{}", error);
+        }
+    }
 }
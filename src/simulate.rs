@@ -0,0 +1,620 @@
+//! `simulate` subcommand: a Modbus TCP server for the datapoints of a
+//! config, to try configs and run the scanner without hardware.
+//!
+//! Values are encoded with the configured data types and byte orders. Each
+//! unit ID and register type gets one bank of registers from the lowest to
+//! the highest configured address; unconfigured registers in between read
+//! as zero. Writes to holding registers and coils are accepted. Device
+//! identification (FC43/14) answers with the basic objects.
+
+use crate::codec::{bitfield32_bit_location, encode_string, encode_value};
+use crate::config::{
+    ByteOrder, Config, DataType, DatapointConfig, Generator, RegisterType, ServerConfig,
+    SimulatedValue,
+};
+use crate::datapoint::{DataValue, Datapoint};
+use crate::headless::shutdown_signal;
+use crate::protocol::{ENCAPSULATED_INTERFACE, READ_DEVICE_ID};
+use crate::scanner::new_datapoint;
+use anyhow::{Context, Result};
+use clap::Args;
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::f64::consts::TAU;
+use std::future::{Ready, ready};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpListener;
+use tokio_modbus::server::tcp::{Server, accept_tcp_connection};
+use tokio_modbus::{Exception, Request, Response, SlaveRequest};
+
+/// Interval at which generated values are updated
+const GENERATOR_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Args, Debug)]
+pub struct SimulateArgs {
+    /// Address to serve on [default: 127.0.0.1 and the server's port]
+    #[arg(long, value_name = "ADDR")]
+    listen: Option<SocketAddr>,
+
+    /// Server whose datapoints are simulated [default: the first]
+    #[arg(long, value_name = "NAME")]
+    server: Option<String>,
+}
+
+/// Serves the datapoints of one server until SIGINT or SIGTERM.
+pub async fn run(config_path: &str, args: &SimulateArgs) -> Result<()> {
+    let config = Config::load(config_path)?;
+    let server = match args.server {
+        Some(ref name) => config
+            .servers
+            .iter()
+            .find(|s| &s.name == name)
+            .with_context(|| format!("No server named '{}'", name))?,
+        None => &config.servers[0],
+    };
+    let datapoints = config.datapoints_for(&server.name);
+    let simulator = Simulator::new(server, datapoints)?;
+
+    let addr = args
+        .listen
+        .unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], server.port)));
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to listen on {}", addr))?;
+    info!(
+        "Simulating {} datapoints of {} on {}",
+        simulator.points.len(),
+        server.name,
+        addr
+    );
+
+    tokio::select! {
+        result = simulator.serve(listener) => result,
+        signal = shutdown_signal() => {
+            info!("Received {}, shutting down", signal?);
+            Ok(())
+        }
+    }
+}
+
+/// Simulated datapoint with the state of its generator.
+struct Point {
+    dp: Datapoint,
+    config: DatapointConfig,
+    order: ByteOrder,
+    /// Current value of a random walk
+    walk: f64,
+    /// Toggles done so far
+    toggles: u64,
+}
+
+pub struct Simulator {
+    points: Vec<Point>,
+    registers: Arc<Mutex<Registers>>,
+}
+
+impl Simulator {
+    /// Sets up the registers of `configs` with their initial values.
+    pub fn new(server: &ServerConfig, configs: Vec<DatapointConfig>) -> Result<Self> {
+        let mut registers = Registers {
+            product_code: server.name.clone(),
+            ..Default::default()
+        };
+        let mut points = Vec::new();
+        for config in configs {
+            let dp = new_datapoint(server, &config, 1);
+            registers.allocate(&dp);
+            let mut point = Point {
+                dp,
+                order: config.byte_order(server),
+                config,
+                walk: 0.0,
+                toggles: 0,
+            };
+            let Some(ref simulation) = point.config.simulate else {
+                points.push(point);
+                continue;
+            };
+
+            if let Some(ref value) = simulation.value {
+                let initial = match *value {
+                    SimulatedValue::Number(number) => from_engineering(&point.dp, number),
+                    _ => point.dp.parse_input(&value.to_string()),
+                }
+                .with_context(|| {
+                    format!("{}: invalid simulation value '{}'", point.dp.name, value)
+                })?;
+                registers.write_point(&point, &initial);
+            }
+            match simulation.generator {
+                Generator::Ramp { min, max, .. }
+                | Generator::Sine { min, max, .. }
+                | Generator::RandomWalk { min, max, .. } => {
+                    for limit in [min, max] {
+                        from_engineering(&point.dp, limit).with_context(|| {
+                            format!("{}: simulation limit {} out of range", point.dp.name, limit)
+                        })?;
+                    }
+                    point.walk = match simulation.value {
+                        Some(SimulatedValue::Number(number)) => number.clamp(min, max),
+                        _ => (min + max) / 2.0,
+                    };
+                }
+                Generator::Constant | Generator::Toggle { .. } => {}
+            }
+            points.push(point);
+        }
+
+        Ok(Self {
+            points,
+            registers: Arc::new(Mutex::new(registers)),
+        })
+    }
+
+    /// Answers Modbus requests on `listener` and keeps the generated
+    /// values moving.
+    pub async fn serve(self, listener: TcpListener) -> Result<()> {
+        let service = Service {
+            registers: self.registers.clone(),
+        };
+        tokio::spawn(self.run_generators());
+
+        let on_connected = |stream, peer| {
+            let service = service.clone();
+            async move {
+                info!("Client connected from {}", peer);
+                accept_tcp_connection(stream, peer, |_| Ok(Some(service.clone())))
+            }
+        };
+        Server::new(listener)
+            .serve(&on_connected, |e| warn!("Client connection failed: {}", e))
+            .await?;
+        Ok(())
+    }
+
+    async fn run_generators(mut self) {
+        let start = Instant::now();
+        let mut random = Random::new();
+        let mut interval = tokio::time::interval(GENERATOR_INTERVAL);
+        let dt = GENERATOR_INTERVAL.as_secs_f64();
+        loop {
+            interval.tick().await;
+            let elapsed = start.elapsed().as_secs_f64();
+            let Ok(mut registers) = self.registers.lock() else {
+                return;
+            };
+            for point in &mut self.points {
+                let Some(ref simulation) = point.config.simulate else {
+                    continue;
+                };
+                let value = match simulation.generator {
+                    Generator::Constant => continue,
+                    Generator::Ramp { min, max, period_s } => {
+                        min + (max - min) * (elapsed / period_s).fract()
+                    }
+                    Generator::Sine { min, max, period_s } => {
+                        min + (max - min) * (1.0 + (TAU * elapsed / period_s).sin()) / 2.0
+                    }
+                    Generator::RandomWalk { min, max, step } => {
+                        let delta = (random.next() * 2.0 - 1.0) * step * dt;
+                        point.walk = (point.walk + delta).clamp(min, max);
+                        point.walk
+                    }
+                    Generator::Toggle { bit, period_s } => {
+                        let toggles = (elapsed / period_s) as u64;
+                        if toggles != point.toggles {
+                            point.toggles = toggles;
+                            registers.toggle_bit(point, bit);
+                        }
+                        continue;
+                    }
+                };
+                match from_engineering(&point.dp, value) {
+                    Ok(value) => registers.write_point(point, &value),
+                    Err(e) => debug!("{}: {}", point.dp.name, e),
+                }
+            }
+        }
+    }
+}
+
+/// Value of `dp` for a number in engineering units. Coils and discrete
+/// inputs take it as a bit mask, lowest address in bit 0.
+fn from_engineering(dp: &Datapoint, value: f64) -> Result<DataValue> {
+    if dp.register_type.is_bit() {
+        let mask = value.round() as u64;
+        let bits: Vec<bool> = (0..dp.length.max(1))
+            .map(|i| i < 64 && mask >> i & 1 != 0)
+            .collect();
+        return Ok(match bits[..] {
+            [bit] => DataValue::Bool(bit),
+            _ => DataValue::Bits(bits),
+        });
+    }
+    match dp.data_type {
+        DataType::Bitfield if dp.length >= 2 => Ok(DataValue::Bitfield32(value as u32)),
+        DataType::Bitfield => Ok(DataValue::Bitfield(value as u16)),
+        DataType::String => Ok(DataValue::String(value.to_string())),
+        data_type => DataValue::from_f64(data_type, (value - dp.offset) / dp.scale),
+    }
+}
+
+/// Registers (or bits, one word each) of one unit ID and register type.
+struct Bank {
+    start: u16,
+    words: Vec<u16>,
+}
+
+#[derive(Default)]
+struct Registers {
+    banks: HashMap<(u8, RegisterType), Bank>,
+    /// Product code reported by device identification
+    product_code: String,
+}
+
+impl Registers {
+    /// Extends the bank of the datapoint to cover its registers.
+    fn allocate(&mut self, dp: &Datapoint) {
+        let end = dp.address as usize + dp.length.max(1) as usize;
+        let bank = self
+            .banks
+            .entry((dp.unit_id, dp.register_type))
+            .or_insert(Bank {
+                start: dp.address,
+                words: Vec::new(),
+            });
+        if dp.address < bank.start {
+            let grow = (bank.start - dp.address) as usize;
+            bank.words.splice(0..0, std::iter::repeat_n(0, grow));
+            bank.start = dp.address;
+        }
+        let len = end.min(u16::MAX as usize + 1) - bank.start as usize;
+        if bank.words.len() < len {
+            bank.words.resize(len, 0);
+        }
+    }
+
+    /// Words of the bank from `address` on, or an exception if they are
+    /// not all served.
+    fn words(
+        &mut self,
+        unit: u8,
+        kind: RegisterType,
+        address: u16,
+        quantity: usize,
+    ) -> Result<&mut [u16], Exception> {
+        self.check_unit(unit)?;
+        let bank = self
+            .banks
+            .get_mut(&(unit, kind))
+            .ok_or(Exception::IllegalDataAddress)?;
+        let offset = address
+            .checked_sub(bank.start)
+            .ok_or(Exception::IllegalDataAddress)? as usize;
+        bank.words
+            .get_mut(offset..offset + quantity)
+            .ok_or(Exception::IllegalDataAddress)
+    }
+
+    /// Units without datapoints are answered as absent behind a gateway.
+    fn check_unit(&self, unit: u8) -> Result<(), Exception> {
+        if self.banks.keys().any(|&(u, _)| u == unit) {
+            Ok(())
+        } else {
+            Err(Exception::GatewayTargetDevice)
+        }
+    }
+
+    /// Answers Read Device Identification with the basic objects, all in
+    /// one response.
+    fn identification(&self, unit: u8, data: &[u8]) -> Result<Response, Exception> {
+        self.check_unit(unit)?;
+        let &[READ_DEVICE_ID, code, object_id] = data else {
+            return Err(Exception::IllegalDataValue);
+        };
+        let objects = [
+            env!("CARGO_PKG_NAME"),
+            self.product_code.as_str(),
+            env!("CARGO_PKG_VERSION"),
+        ];
+        let ids = match code {
+            // Basic, regular and extended stream access; an unknown start
+            // object restarts the stream
+            1..=3 if (object_id as usize) < objects.len() => object_id..objects.len() as u8,
+            1..=3 => 0..objects.len() as u8,
+            // Individual access
+            4 if (object_id as usize) < objects.len() => object_id..object_id + 1,
+            4 => return Err(Exception::IllegalDataAddress),
+            _ => return Err(Exception::IllegalDataValue),
+        };
+        // Basic conformity level with individual access; no more follows
+        let mut response = vec![READ_DEVICE_ID, code, 0x81, 0x00, 0x00, ids.len() as u8];
+        for id in ids {
+            let value = objects[id as usize].as_bytes();
+            response.extend([id, value.len() as u8]);
+            response.extend(value);
+        }
+        Ok(Response::Custom(ENCAPSULATED_INTERFACE, response.into()))
+    }
+
+    fn write_point(&mut self, point: &Point, value: &DataValue) {
+        let words = match value {
+            DataValue::String(text) => encode_string(text, point.dp.length, point.order),
+            _ => encode_value(value, point.order),
+        };
+        let (unit, kind) = (point.dp.unit_id, point.dp.register_type);
+        let length = words.len().min(point.dp.length.max(1) as usize);
+        if let Ok(target) = self.words(unit, kind, point.dp.address, length) {
+            target.copy_from_slice(&words[..length]);
+        }
+    }
+
+    fn toggle_bit(&mut self, point: &Point, bit: u8) {
+        let (unit, kind) = (point.dp.unit_id, point.dp.register_type);
+        let (offset, mask) = if kind.is_bit() {
+            (bit as u16, 1)
+        } else if point.dp.length >= 2 {
+            let (offset, bit) = bitfield32_bit_location(bit, point.order);
+            (offset, 1 << bit)
+        } else {
+            (0, 1 << bit)
+        };
+        if let Ok([word]) = self.words(unit, kind, point.dp.address + offset, 1) {
+            *word ^= mask;
+        }
+    }
+
+    fn handle(&mut self, unit: u8, request: Request) -> Result<Response, Exception> {
+        use RegisterType::*;
+        let read = |registers: &mut Self, kind, address, quantity: u16| {
+            registers
+                .words(unit, kind, address, quantity as usize)
+                .map(|words| words.to_vec())
+        };
+        let bits = |words: Vec<u16>| words.into_iter().map(|w| w != 0).collect();
+        let response = match request {
+            Request::ReadCoils(address, quantity) => {
+                Response::ReadCoils(bits(read(self, Coil, address, quantity)?))
+            }
+            Request::ReadDiscreteInputs(address, quantity) => {
+                Response::ReadDiscreteInputs(bits(read(self, DiscreteInput, address, quantity)?))
+            }
+            Request::ReadHoldingRegisters(address, quantity) => {
+                Response::ReadHoldingRegisters(read(self, Holding, address, quantity)?)
+            }
+            Request::ReadInputRegisters(address, quantity) => {
+                Response::ReadInputRegisters(read(self, Input, address, quantity)?)
+            }
+            Request::WriteSingleCoil(address, coil) => {
+                self.write(unit, Coil, address, &[coil as u16])?;
+                Response::WriteSingleCoil(address, coil)
+            }
+            Request::WriteMultipleCoils(address, ref coils) => {
+                let words: Vec<u16> = coils.iter().map(|&coil| coil as u16).collect();
+                self.write(unit, Coil, address, &words)?;
+                Response::WriteMultipleCoils(address, coils.len() as u16)
+            }
+            Request::WriteSingleRegister(address, word) => {
+                self.write(unit, Holding, address, &[word])?;
+                Response::WriteSingleRegister(address, word)
+            }
+            Request::WriteMultipleRegisters(address, ref words) => {
+                self.write(unit, Holding, address, words)?;
+                Response::WriteMultipleRegisters(address, words.len() as u16)
+            }
+            Request::MaskWriteRegister(address, and_mask, or_mask) => {
+                let word = read(self, Holding, address, 1)?[0];
+                let value = (word & and_mask) | (or_mask & !and_mask);
+                self.write(unit, Holding, address, &[value])?;
+                Response::MaskWriteRegister(address, and_mask, or_mask)
+            }
+            Request::Custom(ENCAPSULATED_INTERFACE, ref data) => self.identification(unit, data)?,
+            _ => return Err(Exception::IllegalFunction),
+        };
+        Ok(response)
+    }
+
+    fn write(
+        &mut self,
+        unit: u8,
+        kind: RegisterType,
+        address: u16,
+        words: &[u16],
+    ) -> Result<(), Exception> {
+        self.words(unit, kind, address, words.len())?
+            .copy_from_slice(words);
+        info!(
+            "Unit {}: wrote {:?} to {:?} at {}",
+            unit, words, kind, address
+        );
+        Ok(())
+    }
+}
+
+/// Request handler shared by all client connections.
+#[derive(Clone)]
+struct Service {
+    registers: Arc<Mutex<Registers>>,
+}
+
+impl tokio_modbus::server::Service for Service {
+    type Request = SlaveRequest<'static>;
+    type Response = Response;
+    type Exception = Exception;
+    type Future = Ready<Result<Response, Exception>>;
+
+    fn call(&self, request: Self::Request) -> Self::Future {
+        let result = match self.registers.lock() {
+            Ok(mut registers) => registers.handle(request.slave, request.request),
+            Err(_) => Err(Exception::ServerDeviceFailure),
+        };
+        ready(result)
+    }
+}
+
+/// Xorshift generator for random walks; quality is not a concern.
+struct Random(u64);
+
+impl Random {
+    fn new() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(1);
+        Self(seed | 1)
+    }
+
+    /// Uniform in `[0, 1)`.
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::Scanner;
+    use serde_json::{Value, json};
+
+    /// Starts a simulator for `datapoints` on a free port and returns a
+    /// scanner connected to it.
+    async fn simulate(server: Value, datapoints: Value) -> Scanner {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut server = server;
+        server["protocol"] = json!("modbus");
+        server["host"] = json!("127.0.0.1");
+        server["port"] = json!(listener.local_addr().unwrap().port());
+        let config = Config::from_json(json!({
+            "server": server,
+            "datapoints": datapoints,
+        }))
+        .unwrap();
+        let server = config.servers[0].clone();
+        let configs = config.datapoints_for(&server.name);
+        let simulator = Simulator::new(&server, configs.clone()).unwrap();
+        tokio::spawn(simulator.serve(listener));
+        Scanner::new(server, configs, 1)
+    }
+
+    fn values(scanner: &Scanner) -> Vec<String> {
+        scanner
+            .get_datapoints()
+            .iter()
+            .map(|dp| match dp.error {
+                Some(ref error) => error.clone(),
+                None => dp.display_value().unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn scanner_reads_initial_values() {
+        let mut scanner = simulate(
+            json!({ "endianness": "little" }),
+            json!([
+                { "name": "temp", "address": 0, "length": 1, "scale": 0.1, "unit": "°C",
+                  "simulate": { "generator": "constant", "value": 21.5 } },
+                { "name": "power", "address": 1, "length": 2, "data_type": "f32",
+                  "simulate": { "generator": "constant", "value": 3.25 } },
+                { "name": "energy", "address": 3, "length": 4, "data_type": "u64",
+                  "byte_order": "BADC",
+                  "simulate": { "generator": "constant", "value": 123456789 } },
+                { "name": "state", "address": 7, "length": 1, "enum": { "0": "Off", "1": "Run" },
+                  "simulate": { "generator": "constant", "value": "run" } },
+                { "name": "label", "address": 8, "length": 3, "data_type": "string",
+                  "simulate": { "generator": "constant", "value": "PUMP-1" } },
+                { "name": "inputs", "address": 0, "length": 3, "register_type": "discrete_input",
+                  "simulate": { "generator": "constant", "value": 5 } },
+                { "name": "volts", "address": 50, "length": 1, "register_type": "input",
+                  "unit_id": 2, "simulate": { "generator": "constant", "value": 230 } },
+                { "name": "plain", "address": 20, "length": 1 },
+            ]),
+        )
+        .await;
+        scanner.scan_once().await.unwrap();
+        assert_eq!(
+            values(&scanner),
+            [
+                "21.5 °C",
+                "3.25",
+                "123456789",
+                "Run (1)",
+                "PUMP-1",
+                "101",
+                "230",
+                "0"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn scanner_writes_are_kept() {
+        let mut scanner = simulate(
+            json!({ "mask_write": true }),
+            json!([
+                { "name": "setpoint", "address": 10, "length": 2, "data_type": "i32",
+                  "byte_order": "CDAB", "writable": true },
+                { "name": "flags", "address": 12, "length": 1, "data_type": "bitfield",
+                  "writable": true,
+                  "simulate": { "generator": "constant", "value": 1 } },
+                { "name": "run", "address": 3, "length": 1, "register_type": "coil",
+                  "writable": true },
+            ]),
+        )
+        .await;
+        scanner.scan_once().await.unwrap();
+        scanner
+            .write_datapoint(0, DataValue::I32(-70000))
+            .await
+            .unwrap();
+        scanner.write_bit(1, 4, true).await.unwrap();
+        scanner
+            .write_datapoint(2, DataValue::Bool(true))
+            .await
+            .unwrap();
+        scanner.scan_once().await.unwrap();
+        assert_eq!(values(&scanner), ["-70000", "0x0011", "ON"]);
+    }
+
+    #[test]
+    fn serves_the_configured_span_only() {
+        let config = Config::from_json(json!({
+            "server": { "protocol": "modbus", "host": "127.0.0.1" },
+            "datapoints": [
+                { "name": "first", "address": 5, "length": 1 },
+                { "name": "last", "address": 8, "length": 1 },
+            ],
+        }))
+        .unwrap();
+        let mut registers = Registers::default();
+        for dp in &config.datapoints {
+            registers.allocate(&new_datapoint(&config.servers[0], dp, 1));
+        }
+
+        // Unconfigured registers between datapoints read as zero
+        assert!(matches!(
+            registers.handle(1, Request::ReadHoldingRegisters(5, 4)),
+            Ok(Response::ReadHoldingRegisters(words)) if words == [0; 4]
+        ));
+        for request in [
+            Request::ReadHoldingRegisters(4, 2),
+            Request::ReadHoldingRegisters(8, 2),
+            Request::ReadInputRegisters(5, 1),
+            Request::WriteSingleRegister(9, 1),
+        ] {
+            assert!(matches!(
+                registers.handle(1, request),
+                Err(Exception::IllegalDataAddress)
+            ));
+        }
+        assert!(matches!(
+            registers.handle(2, Request::ReadHoldingRegisters(5, 1)),
+            Err(Exception::GatewayTargetDevice)
+        ));
+    }
+}
@@ -1,37 +1,438 @@
-use crate::datapoint::Datapoint;
+use crate::config::Severity;
+use crate::datapoint::{BitStatus, DataValue, Datapoint, MAX_TIMELINE_BITS};
+use crate::events::{Event, EventKind, EventLog};
+use crate::history::sparkline;
+use crate::mqtt::MqttStatus;
+use crate::replay::{ReplayStatus, format_offset};
+use crate::scanner::ConnectionState;
+use chrono::{DateTime, Local};
 use ratatui::{
     Frame,
     layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
+    symbols::Marker,
     text::{Line, Span},
-    widgets::{Block, Borders, Cell, Paragraph, Row, Table, TableState},
+    widgets::{
+        Axis, Block, Borders, Cell, Chart, Clear, Dataset, GraphType, Paragraph, Row, Table,
+        TableState, Wrap,
+    },
 };
+use std::ops::Range;
+use std::time::Duration;
+
+/// Number of recent samples shown in the Trend column.
+const TREND_SAMPLES: usize = 12;
+
+/// Lines of a string value shown in the details panel before it is cut off.
+const MAX_TEXT_LINES: usize = 6;
+
+/// Enum labels shown at once in the write dialog.
+const MAX_PICKER_LINES: usize = 10;
 
 pub struct App {
+    /// Datapoints of all servers, grouped by server
     pub datapoints: Vec<Datapoint>,
     pub table_state: TableState,
-    pub server_info: String,
+    pub servers: Vec<ServerView>,
+    /// Only show the datapoints of this server
+    pub server_filter: Option<usize>,
     pub scan_interval: u64,
+    pub edit: Option<EditDialog>,
+    /// Cursor position in the bitfield panel while it has focus
+    pub bit_cursor: Option<usize>,
+    pub pending_bit: Option<BitToggle>,
+    /// Time window of the history chart while it is shown
+    pub chart: Option<ChartWindow>,
+    /// Show raw decoded values and register words instead of scaled values
+    pub show_raw: bool,
+    pub events: EventLog,
+    /// Cursor in the event log (newest first) while it is shown
+    pub log_cursor: Option<usize>,
+    /// File scans are being recorded to
+    pub recording: Option<String>,
+    /// Connection to the MQTT broker, if publishing
+    pub mqtt: Option<MqttStatus>,
+    /// Playback position while replaying a recording
+    pub replay: Option<ReplayStatus>,
+    pub seek: Option<SeekPrompt>,
+}
+
+pub struct ServerView {
+    pub name: String,
+    pub info: String,
+    pub connection: ConnectionState,
+    /// Position of this server's datapoints in `App::datapoints`
+    pub range: Range<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChartWindow {
+    OneMinute,
+    TenMinutes,
+    OneHour,
+}
+
+impl ChartWindow {
+    pub fn duration(self) -> Duration {
+        match self {
+            ChartWindow::OneMinute => Duration::from_secs(60),
+            ChartWindow::TenMinutes => Duration::from_secs(600),
+            ChartWindow::OneHour => Duration::from_secs(3600),
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ChartWindow::OneMinute => "1 min",
+            ChartWindow::TenMinutes => "10 min",
+            ChartWindow::OneHour => "1 h",
+        }
+    }
+
+    fn next(self) -> Self {
+        match self {
+            ChartWindow::OneMinute => ChartWindow::TenMinutes,
+            ChartWindow::TenMinutes => ChartWindow::OneHour,
+            ChartWindow::OneHour => ChartWindow::OneMinute,
+        }
+    }
+}
+
+/// Value entry popup for writing a datapoint.
+pub struct EditDialog {
+    pub index: usize,
+    pub input: String,
+    pub error: Option<String>,
+    /// Labels of an enum datapoint to pick from with the arrow keys
+    pub options: Vec<String>,
+    pub option: Option<usize>,
+}
+
+/// Time entry popup for seeking in a replay.
+pub struct SeekPrompt {
+    pub input: String,
+    pub error: Option<String>,
+}
+
+/// Bit change waiting for confirmation.
+pub struct BitToggle {
+    pub index: usize,
+    pub bit: u8,
+    pub name: String,
+    pub value: bool,
 }
 
 impl App {
-    pub fn new(server_info: String, scan_interval: u64) -> Self {
+    pub fn new(scan_interval: u64, events: EventLog) -> Self {
         Self {
             datapoints: Vec::new(),
             table_state: TableState::default(),
-            server_info,
+            servers: Vec::new(),
+            server_filter: None,
             scan_interval,
+            edit: None,
+            bit_cursor: None,
+            pending_bit: None,
+            chart: None,
+            show_raw: false,
+            events,
+            log_cursor: None,
+            recording: None,
+            mqtt: None,
+            replay: None,
+            seek: None,
+        }
+    }
+
+    /// Current time, or the playback position while replaying.
+    pub fn now(&self) -> DateTime<Local> {
+        match self.replay {
+            Some(ref replay) => replay.clock,
+            None => Local::now(),
+        }
+    }
+
+    /// Drops all events, e.g. when a replay starts over.
+    pub fn clear_events(&mut self) {
+        self.events = EventLog::new();
+        if self.log_cursor.is_some() {
+            self.log_cursor = Some(0);
+        }
+    }
+
+    pub fn open_seek(&mut self) {
+        self.seek = Some(SeekPrompt {
+            input: String::new(),
+            error: None,
+        });
+    }
+
+    /// Records events collected from a scanner, keeping the cursor on the
+    /// selected event unless it is on the newest one.
+    pub fn log_events(&mut self, events: Vec<Event>) {
+        let count = events.len();
+        for event in events {
+            self.events.record(event);
+        }
+        if let Some(cursor) = self.log_cursor.as_mut()
+            && *cursor > 0
+        {
+            *cursor = (*cursor + count).min(self.events.events().len().saturating_sub(1));
         }
     }
 
-    pub fn update_datapoints(&mut self, datapoints: Vec<Datapoint>) {
-        self.datapoints = datapoints;
+    pub fn toggle_log(&mut self) {
+        self.log_cursor = match self.log_cursor {
+            Some(_) => None,
+            None => Some(0),
+        };
+    }
+
+    pub fn next_event(&mut self) {
+        let count = self.events.events().len();
+        if let Some(cursor) = self.log_cursor.as_mut()
+            && *cursor + 1 < count
+        {
+            *cursor += 1;
+        }
+    }
+
+    pub fn previous_event(&mut self) {
+        if let Some(cursor) = self.log_cursor.as_mut() {
+            *cursor = cursor.saturating_sub(1);
+        }
+    }
+
+    /// Acknowledges the alarm under the log cursor.
+    pub fn acknowledge_event(&mut self) {
+        let Some(cursor) = self.log_cursor else {
+            return;
+        };
+        let count = self.events.events().len();
+        if cursor < count && self.events.acknowledge(count - 1 - cursor) {
+            // The acknowledgement is logged as a new event on top
+            self.log_cursor = Some(cursor + 1);
+        }
+    }
+
+    pub fn toggle_raw(&mut self) {
+        self.show_raw = !self.show_raw;
+    }
+
+    pub fn toggle_chart(&mut self) {
+        self.chart = match self.chart {
+            Some(_) => None,
+            None => Some(ChartWindow::OneMinute),
+        };
+    }
+
+    pub fn next_chart_window(&mut self) {
+        if let Some(window) = self.chart {
+            self.chart = Some(window.next());
+        }
+    }
+
+    pub fn add_server(&mut self, name: String, info: String, datapoints: Vec<Datapoint>) {
+        let start = self.datapoints.len();
+        self.datapoints.extend(datapoints);
+        self.servers.push(ServerView {
+            name,
+            info,
+            connection: ConnectionState::Idle,
+            range: start..self.datapoints.len(),
+        });
+    }
+
+    /// Replaces the datapoints and connection state of server `server`.
+    pub fn update_server(
+        &mut self,
+        server: usize,
+        datapoints: Vec<Datapoint>,
+        connection: ConnectionState,
+    ) {
+        let view = &mut self.servers[server];
+        view.connection = connection;
+        self.datapoints.splice(view.range.clone(), datapoints);
+    }
+
+    /// Maps an index into `datapoints` to the server index and the
+    /// datapoint index within that server.
+    pub fn locate(&self, index: usize) -> Option<(usize, usize)> {
+        self.servers
+            .iter()
+            .position(|s| s.range.contains(&index))
+            .map(|server| (server, index - self.servers[server].range.start))
+    }
+
+    /// Indices into `datapoints` shown in the table with the current filter.
+    pub fn visible(&self) -> Range<usize> {
+        match self.server_filter.and_then(|i| self.servers.get(i)) {
+            Some(server) => server.range.clone(),
+            None => 0..self.datapoints.len(),
+        }
+    }
+
+    pub fn selected_index(&self) -> Option<usize> {
+        let visible = self.visible();
+        self.table_state
+            .selected()
+            .map(|i| visible.start + i)
+            .filter(|i| visible.contains(i))
+    }
+
+    pub fn selected_datapoint(&self) -> Option<&Datapoint> {
+        self.selected_index().and_then(|i| self.datapoints.get(i))
+    }
+
+    /// Cycles the table between all servers and each single server.
+    pub fn next_server_filter(&mut self) {
+        self.server_filter = match self.server_filter {
+            None if !self.servers.is_empty() => Some(0),
+            Some(i) if i + 1 < self.servers.len() => Some(i + 1),
+            _ => None,
+        };
+        self.table_state.select(None);
+        self.bit_cursor = None;
+    }
+
+    /// Opens the edit dialog for the selected datapoint if it is writable.
+    pub fn open_edit(&mut self) {
+        if let Some(idx) = self.selected_index()
+            && let Some(dp) = self.datapoints.get(idx)
+            && dp.writable
+        {
+            let options: Vec<String> = dp
+                .enum_labels
+                .iter()
+                .map(|(code, label)| format!("{} ({})", label.label(), code))
+                .collect();
+            let current = dp.value.as_ref().and_then(|v| v.as_i64());
+            self.edit = Some(EditDialog {
+                index: idx,
+                input: dp
+                    .value
+                    .as_ref()
+                    .map(|v| dp.format_number(v))
+                    .unwrap_or_default(),
+                error: None,
+                options,
+                option: current.and_then(|c| dp.enum_labels.keys().position(|k| *k == c)),
+            });
+        }
+    }
+
+    /// Moves the enum picker by `step` and fills in the chosen label.
+    pub fn move_edit_option(&mut self, step: isize) {
+        let Some(edit) = self.edit.as_mut() else {
+            return;
+        };
+        if edit.options.is_empty() {
+            return;
+        }
+        let count = edit.options.len() as isize;
+        let next = match edit.option {
+            Some(i) => (i as isize + step).rem_euclid(count),
+            None if step < 0 => count - 1,
+            None => 0,
+        } as usize;
+        edit.option = Some(next);
+        edit.input = edit.options[next].clone();
+        edit.error = None;
+    }
+
+    pub fn cancel_edit(&mut self) {
+        self.edit = None;
+    }
+
+    /// Parses the dialog input. Closes the dialog and returns the value to
+    /// write on success, keeps it open with an error message otherwise.
+    pub fn submit_edit(&mut self) -> Option<(usize, DataValue)> {
+        let edit = self.edit.as_mut()?;
+        let dp = self.datapoints.get(edit.index)?;
+        match dp.parse_input(&edit.input) {
+            Ok(value) => {
+                let index = edit.index;
+                self.edit = None;
+                Some((index, value))
+            }
+            Err(e) => {
+                edit.error = Some(e.to_string());
+                None
+            }
+        }
+    }
+
+    /// Moves focus into the bitfield panel of the selected datapoint.
+    pub fn focus_bits(&mut self) {
+        if let Some(idx) = self.selected_index()
+            && let Some(dp) = self.datapoints.get(idx)
+            && dp.writable
+            && dp.get_bitfield_status().is_some()
+        {
+            self.bit_cursor = Some(0);
+        }
+    }
+
+    pub fn unfocus_bits(&mut self) {
+        self.bit_cursor = None;
+    }
+
+    fn selected_bits(&self) -> Vec<BitStatus> {
+        self.selected_datapoint()
+            .and_then(|dp| dp.get_bitfield_status())
+            .unwrap_or_default()
+    }
+
+    pub fn next_bit(&mut self) {
+        let count = self.selected_bits().len();
+        if let Some(cursor) = self.bit_cursor.as_mut() {
+            *cursor = if *cursor + 1 >= count { 0 } else { *cursor + 1 };
+        }
+    }
+
+    pub fn previous_bit(&mut self) {
+        let count = self.selected_bits().len();
+        if let Some(cursor) = self.bit_cursor.as_mut() {
+            *cursor = if *cursor == 0 {
+                count.saturating_sub(1)
+            } else {
+                *cursor - 1
+            };
+        }
+    }
+
+    /// Asks for confirmation to flip the bit under the cursor.
+    /// Read-only bits and multi-bit fields are ignored.
+    pub fn request_bit_toggle(&mut self) {
+        let (Some(cursor), Some(idx)) = (self.bit_cursor, self.selected_index()) else {
+            return;
+        };
+        let Some(field) = self.selected_bits().into_iter().nth(cursor) else {
+            return;
+        };
+        if !field.is_flag() || self.datapoints[idx].read_only_bits.contains(&field.bit) {
+            return;
+        }
+        self.pending_bit = Some(BitToggle {
+            index: idx,
+            bit: field.bit,
+            value: !field.is_set(),
+            name: field.name,
+        });
+    }
+
+    pub fn confirm_bit_toggle(&mut self) -> Option<BitToggle> {
+        self.pending_bit.take()
+    }
+
+    pub fn cancel_bit_toggle(&mut self) {
+        self.pending_bit = None;
     }
 
     pub fn next(&mut self) {
         let i = match self.table_state.selected() {
             Some(i) => {
-                if i >= self.datapoints.len().saturating_sub(1) {
+                if i >= self.visible().len().saturating_sub(1) {
                     0
                 } else {
                     i + 1
@@ -46,7 +447,7 @@ impl App {
         let i = match self.table_state.selected() {
             Some(i) => {
                 if i == 0 {
-                    self.datapoints.len().saturating_sub(1)
+                    self.visible().len().saturating_sub(1)
                 } else {
                     i - 1
                 }
@@ -58,92 +459,220 @@ impl App {
 }
 
 pub fn draw(f: &mut Frame, app: &mut App) {
-    // Check if we need to show error details or bitfield details
-    let (show_error_details, show_bitfield_details) = if let Some(idx) = app.table_state.selected()
-    {
-        if let Some(dp) = app.datapoints.get(idx) {
-            (dp.error.is_some(), dp.get_bitfield_status().is_some())
-        } else {
-            (false, false)
-        }
-    } else {
-        (false, false)
-    };
+    // Check if we need to show error, bitfield or text details
+    let (show_error_details, show_bitfield_details, show_write_status, text_len) =
+        match app.selected_datapoint() {
+            Some(dp) => (
+                dp.error.is_some(),
+                dp.get_bitfield_status().is_some(),
+                dp.write_status.is_some(),
+                match dp.value {
+                    Some(DataValue::String(ref text)) => Some(text.chars().count()),
+                    _ => None,
+                },
+            ),
+            None => (false, false, false, None),
+        };
+    let show_text_details = text_len.is_some() && !show_error_details;
 
-    let show_details = show_error_details || show_bitfield_details;
-
-    let chunks = if show_details {
-        Layout::default()
-            .direction(Direction::Vertical)
-            .constraints([
-                Constraint::Length(3),
-                Constraint::Min(0),
-                Constraint::Length(if show_bitfield_details { 10 } else { 4 }),
-                Constraint::Length(3),
-            ])
-            .split(f.area())
+    let show_details =
+        show_error_details || show_bitfield_details || show_text_details || show_write_status;
+    let details_height = if show_error_details {
+        4 + show_write_status as u16
+    } else if show_bitfield_details {
+        10
+    } else if let Some(len) = text_len {
+        let width = f.area().width.saturating_sub(2).max(1) as usize;
+        len.div_ceil(width).clamp(1, MAX_TEXT_LINES) as u16 + 2 + show_write_status as u16
     } else {
-        Layout::default()
-            .direction(Direction::Vertical)
-            .constraints([
-                Constraint::Length(3),
-                Constraint::Min(0),
-                Constraint::Length(3),
-            ])
-            .split(f.area())
+        3
     };
 
+    let mut constraints = vec![Constraint::Length(3), Constraint::Min(0)];
+    if app.chart.is_some() {
+        constraints.push(Constraint::Percentage(45));
+    }
+    if app.log_cursor.is_some() {
+        constraints.push(Constraint::Percentage(40));
+    }
+    if show_details {
+        constraints.push(Constraint::Length(details_height));
+    }
+    constraints.push(Constraint::Length(3));
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(constraints)
+        .split(f.area());
+
     draw_header(f, chunks[0], app);
     draw_datapoints_table(f, chunks[1], app);
 
+    let mut next = 2;
+    if app.chart.is_some() {
+        draw_chart(f, chunks[next], app);
+        next += 1;
+    }
+    if app.log_cursor.is_some() {
+        draw_event_log(f, chunks[next], app);
+        next += 1;
+    }
     if show_details {
         if show_error_details {
-            draw_error_details(f, chunks[2], app);
+            draw_error_details(f, chunks[next], app);
         } else if show_bitfield_details {
-            draw_bitfield_details(f, chunks[2], app);
+            draw_bitfield_details(f, chunks[next], app);
+        } else if show_text_details {
+            draw_text_details(f, chunks[next], app);
+        } else {
+            draw_write_details(f, chunks[next], app);
         }
-        draw_footer(f, chunks[3]);
-    } else {
-        draw_footer(f, chunks[2]);
+        next += 1;
+    }
+    draw_footer(f, chunks[next], app);
+
+    if app.edit.is_some() {
+        draw_edit_dialog(f, app);
+    }
+    if app.seek.is_some() {
+        draw_seek_prompt(f, app);
+    }
+    if app.pending_bit.is_some() {
+        draw_bit_confirm(f, app);
     }
 }
 
-fn draw_header(f: &mut Frame, area: Rect, app: &App) {
-    let error_count = app
-        .datapoints
+/// Active alarm count and highest severity of a set of datapoints.
+fn alarm_summary(datapoints: &[Datapoint]) -> (usize, Option<Severity>) {
+    let active = datapoints
         .iter()
-        .filter(|dp| dp.error.is_some())
-        .count();
-    let ok_count = app
-        .datapoints
-        .iter()
-        .filter(|dp| dp.value.is_some())
-        .count();
-    let total = app.datapoints.len();
+        .flat_map(|dp| dp.alarms.iter())
+        .filter(|alarm| alarm.is_active());
+    active.fold((0, None), |(count, highest), alarm| {
+        (count + 1, highest.max(Some(alarm.severity())))
+    })
+}
 
-    let status = if error_count == total && total > 0 {
-        Span::styled(
-            " [ALL ERRORS]",
-            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
-        )
-    } else if error_count > 0 {
-        Span::styled(
-            format!(" [{} errors]", error_count),
-            Style::default().fg(Color::Yellow),
-        )
-    } else if ok_count > 0 {
-        Span::styled(" [Connected]", Style::default().fg(Color::Green))
-    } else {
-        Span::styled(" [Waiting...]", Style::default().fg(Color::Gray))
+fn severity_color(severity: Severity) -> Color {
+    match severity {
+        Severity::Info => Color::Cyan,
+        Severity::Warning => Color::Yellow,
+        Severity::Critical => Color::Red,
+    }
+}
+
+fn connection_span(
+    connection: &ConnectionState,
+    error_count: usize,
+    (alarm_count, severity): (usize, Option<Severity>),
+) -> Span<'static> {
+    match *connection {
+        ConnectionState::Idle => Span::styled(" [Waiting...]", Style::default().fg(Color::Gray)),
+        ConnectionState::Connecting => {
+            Span::styled(" [Connecting...]", Style::default().fg(Color::Yellow))
+        }
+        ConnectionState::Connected => {
+            let mut status = "Connected".to_string();
+            if error_count > 0 {
+                status.push_str(&format!(", {} errors", error_count));
+            }
+            if alarm_count > 0 {
+                status.push_str(&format!(", {} alarms", alarm_count));
+            }
+            let color = match severity {
+                Some(Severity::Critical) => Color::Red,
+                _ if error_count > 0 || alarm_count > 0 => Color::Yellow,
+                _ => Color::Green,
+            };
+            Span::styled(format!(" [{}]", status), Style::default().fg(color))
+        }
+        ConnectionState::Backoff { until, attempt } => {
+            let remaining = until.saturating_duration_since(tokio::time::Instant::now());
+            Span::styled(
+                format!(
+                    " [Disconnected, retry #{} in {:.1}s]",
+                    attempt,
+                    remaining.as_secs_f32()
+                ),
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            )
+        }
+    }
+}
+
+fn draw_header(f: &mut Frame, area: Rect, app: &App) {
+    let error_count = |server: &ServerView| {
+        app.datapoints[server.range.clone()]
+            .iter()
+            .filter(|dp| dp.error.is_some())
+            .count()
     };
 
-    let title = Line::from(vec![
-        Span::raw(format!(
-            "Datapoint Monitor - {} | Scan Interval: {}ms",
-            app.server_info, app.scan_interval
-        )),
-        status,
-    ]);
+    let mut title = match app.servers.as_slice() {
+        [server] => Line::from(vec![
+            Span::raw(format!(
+                "Datapoint Monitor - {} | Scan Interval: {}ms",
+                server.info, app.scan_interval
+            )),
+            connection_span(
+                &server.connection,
+                error_count(server),
+                alarm_summary(&app.datapoints[server.range.clone()]),
+            ),
+        ]),
+        servers => {
+            let mut spans = vec![Span::raw(format!(
+                "Datapoint Monitor | Scan Interval: {}ms |",
+                app.scan_interval
+            ))];
+            for server in servers {
+                spans.push(Span::raw(format!(" {}", server.name)));
+                spans.push(connection_span(
+                    &server.connection,
+                    error_count(server),
+                    alarm_summary(&app.datapoints[server.range.clone()]),
+                ));
+            }
+            Line::from(spans)
+        }
+    };
+    if let Some(ref replay) = app.replay {
+        title.push_span(Span::styled(
+            format!(
+                " | {} REPLAY {} ({}/{}) {}x",
+                if replay.paused { "⏸" } else { "▶" },
+                replay.clock.format("%H:%M:%S"),
+                format_offset(replay.clock - replay.start),
+                format_offset(replay.end - replay.start),
+                replay.speed
+            ),
+            Style::default().fg(Color::Magenta),
+        ));
+    }
+    if let Some(ref path) = app.recording {
+        title.push_span(Span::styled(
+            format!(" | ● REC {}", path),
+            Style::default().fg(Color::Red),
+        ));
+    }
+    if let Some(ref mqtt) = app.mqtt {
+        let (text, color) = match mqtt {
+            MqttStatus::Connecting => ("connecting", Color::Yellow),
+            MqttStatus::Connected => ("connected", Color::Green),
+            MqttStatus::Disconnected(_) => ("disconnected", Color::Red),
+        };
+        title.push_span(Span::styled(
+            format!(" | MQTT {}", text),
+            Style::default().fg(color),
+        ));
+    }
+    let unacknowledged = app.events.unacknowledged();
+    if unacknowledged > 0 {
+        title.push_span(Span::styled(
+            format!(" | {} unacknowledged alarms (l)", unacknowledged),
+            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+        ));
+    }
 
     let header = Paragraph::new(title)
         .style(
@@ -156,28 +685,55 @@ fn draw_header(f: &mut Frame, area: Rect, app: &App) {
 }
 
 fn draw_datapoints_table(f: &mut Frame, area: Rect, app: &mut App) {
-    let header_cells = ["Name", "Address", "Type", "Value", "Status", "Last Updated"]
-        .iter()
-        .map(|h| {
-            Cell::from(*h).style(
-                Style::default()
-                    .fg(Color::Yellow)
-                    .add_modifier(Modifier::BOLD),
-            )
-        });
+    let multi_server = app.servers.len() > 1;
+    let columns: &[&str] = if multi_server {
+        &[
+            "Server",
+            "Name",
+            "Address",
+            "Type",
+            "Value",
+            "Trend",
+            "Status",
+            "Last Updated",
+        ]
+    } else {
+        &[
+            "Name",
+            "Address",
+            "Type",
+            "Value",
+            "Trend",
+            "Status",
+            "Last Updated",
+        ]
+    };
+    let header_cells = columns.iter().map(|h| {
+        Cell::from(*h).style(
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        )
+    });
     let header = Row::new(header_cells).height(1).bottom_margin(1);
 
-    let rows = app.datapoints.iter().map(|dp| {
+    let show_raw = app.show_raw;
+    let rows = app.datapoints[app.visible()].iter().map(|dp| {
+        let alarm = dp.active_alarm().filter(|_| dp.error.is_none());
         let (status, status_color, value_str, data_type) = if let Some(ref error) = dp.error {
             // Parse error message to show friendly status
             let status_text = if error.contains("Connection timeout") {
                 "TIMEOUT"
             } else if error.contains("Connection failed") || error.contains("Connection refused") {
                 "CONN FAIL"
+            } else if error.contains("Connection lost") {
+                "CONN LOST"
             } else if error.contains("Read timeout") {
                 "READ TMO"
             } else if error.contains("Modbus exception") {
                 "MODBUS ERR"
+            } else if error.contains("Short read") {
+                "SHORT READ"
             } else if error.contains("Read error") {
                 "READ ERR"
             } else {
@@ -185,10 +741,19 @@ fn draw_datapoints_table(f: &mut Frame, area: Rect, app: &mut App) {
             };
             (status_text, Color::Red, "-".to_string(), "-".to_string())
         } else if let Some(ref value) = dp.value {
+            let value_str = if show_raw {
+                dp.raw_display()
+            } else {
+                dp.display_value()
+            };
+            let (status, color) = match alarm {
+                Some(alarm) => (alarm.label(), severity_color(alarm.severity())),
+                None => ("OK", Color::Green),
+            };
             (
-                "OK",
-                Color::Green,
-                value.to_string(),
+                status,
+                color,
+                value_str.unwrap_or_default(),
                 value.type_name().to_string(),
             )
         } else {
@@ -200,34 +765,90 @@ fn draw_datapoints_table(f: &mut Frame, area: Rect, app: &mut App) {
             .map(|t| t.format("%H:%M:%S").to_string())
             .unwrap_or_else(|| "-".to_string());
 
-        let cells = vec![
+        // Enum colors, unknown codes stand out
+        let value_style = match dp.value.as_ref().and_then(|v| dp.enum_label(v)) {
+            _ if dp.error.is_some() || show_raw => Style::default(),
+            Some(Some(label)) => label
+                .color()
+                .and_then(|c| c.parse::<Color>().ok())
+                .map(|c| Style::default().fg(c))
+                .unwrap_or_default(),
+            Some(None) => Style::default()
+                .fg(Color::Magenta)
+                .add_modifier(Modifier::BOLD),
+            None => Style::default(),
+        };
+
+        let trend = if dp.has_bit_timeline() {
+            String::new()
+        } else {
+            let values: Vec<f64> = dp.history.latest(TREND_SAMPLES).map(|s| s.value).collect();
+            sparkline(&values)
+        };
+
+        let mut cells = Vec::with_capacity(columns.len());
+        if multi_server {
+            cells.push(Cell::from(dp.server.clone()));
+        }
+        cells.extend([
             Cell::from(dp.name.clone()),
             Cell::from(format!("{}", dp.address)),
             Cell::from(data_type).style(Style::default().fg(Color::Cyan)),
-            Cell::from(value_str),
+            Cell::from(value_str).style(value_style),
+            Cell::from(trend).style(Style::default().fg(Color::Cyan)),
             Cell::from(status).style(
                 Style::default()
                     .fg(status_color)
                     .add_modifier(Modifier::BOLD),
             ),
             Cell::from(last_updated),
-        ];
+        ]);
 
-        Row::new(cells).height(1)
+        // Rows in alarm take the color of the highest active severity
+        let row_style = match alarm {
+            Some(alarm) => Style::default().fg(severity_color(alarm.severity())),
+            None => Style::default(),
+        };
+        Row::new(cells).height(1).style(row_style)
     });
 
-    let widths = [
-        Constraint::Percentage(25),
-        Constraint::Percentage(10),
-        Constraint::Percentage(8),
-        Constraint::Percentage(20),
-        Constraint::Percentage(12),
-        Constraint::Percentage(25),
-    ];
+    let widths = if multi_server {
+        vec![
+            Constraint::Percentage(11),
+            Constraint::Percentage(19),
+            Constraint::Percentage(8),
+            Constraint::Percentage(7),
+            Constraint::Percentage(15),
+            Constraint::Percentage(13),
+            Constraint::Percentage(11),
+            Constraint::Percentage(16),
+        ]
+    } else {
+        vec![
+            Constraint::Percentage(22),
+            Constraint::Percentage(9),
+            Constraint::Percentage(7),
+            Constraint::Percentage(17),
+            Constraint::Percentage(14),
+            Constraint::Percentage(11),
+            Constraint::Percentage(20),
+        ]
+    };
+
+    let title = match app.server_filter.and_then(|i| app.servers.get(i)) {
+        Some(server) => format!("Datapoints - {} (Tab: next server)", server.name),
+        None if multi_server => "Datapoints - all servers (Tab: filter)".to_string(),
+        None => "Datapoints".to_string(),
+    };
+    let title = if app.show_raw {
+        format!("{} - raw values", title)
+    } else {
+        title
+    };
 
     let table = Table::new(rows, widths)
         .header(header)
-        .block(Block::default().borders(Borders::ALL).title("Datapoints"))
+        .block(Block::default().borders(Borders::ALL).title(title))
         .row_highlight_style(
             Style::default()
                 .bg(Color::DarkGray)
@@ -238,18 +859,142 @@ fn draw_datapoints_table(f: &mut Frame, area: Rect, app: &mut App) {
     f.render_stateful_widget(table, area, &mut app.table_state);
 }
 
+fn draw_chart(f: &mut Frame, area: Rect, app: &App) {
+    let window = app.chart.unwrap_or(ChartWindow::OneMinute);
+    let Some(dp) = app.selected_datapoint() else {
+        let placeholder = Paragraph::new("Select a datapoint to show its history").block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!("History ({})", window.label())),
+        );
+        f.render_widget(placeholder, area);
+        return;
+    };
+
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(format!(
+            "History - {} ({}, t: change window, g: close)",
+            dp.name,
+            window.label()
+        ))
+        .style(Style::default().fg(Color::Cyan));
+
+    let now = app.now();
+    if dp.has_bit_timeline() {
+        draw_bit_timeline(f, area, block, dp, window, now);
+        return;
+    }
+
+    let points: Vec<(f64, f64)> = dp
+        .history
+        .window(now, window.duration())
+        .map(|s| {
+            let age = (s.timestamp - now).num_milliseconds() as f64 / 1000.0;
+            (age, s.value)
+        })
+        .collect();
+
+    let (mut min, mut max) = points
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), (_, v)| {
+            (lo.min(*v), hi.max(*v))
+        });
+    if points.is_empty() {
+        (min, max) = (0.0, 1.0);
+    } else if min == max {
+        min -= 1.0;
+        max += 1.0;
+    }
+
+    let dataset = Dataset::default()
+        .name(dp.name.clone())
+        .marker(Marker::Braille)
+        .graph_type(GraphType::Line)
+        .style(Style::default().fg(Color::Yellow))
+        .data(&points);
+
+    let chart = Chart::new(vec![dataset])
+        .block(block)
+        .legend_position(None)
+        .x_axis(
+            Axis::default()
+                .style(Style::default().fg(Color::Gray))
+                .bounds([-window.duration().as_secs_f64(), 0.0])
+                .labels([format!("-{}", window.label()), "now".to_string()]),
+        )
+        .y_axis(
+            Axis::default()
+                .style(Style::default().fg(Color::Gray))
+                .bounds([min, max])
+                .labels([
+                    format!("{:.2}", min),
+                    format!("{:.2}", (min + max) / 2.0),
+                    format!("{:.2}", max),
+                ]),
+        );
+    f.render_widget(chart, area);
+}
+
+/// Logic analyzer style view: one row per bit, high as a filled block.
+fn draw_bit_timeline(
+    f: &mut Frame,
+    area: Rect,
+    block: Block,
+    dp: &Datapoint,
+    window: ChartWindow,
+    now: DateTime<Local>,
+) {
+    let bits = dp.timeline_bits();
+    let label_width = bits
+        .iter()
+        .map(|(_, name)| name.chars().count())
+        .max()
+        .unwrap_or(0)
+        .min(24);
+    // Borders plus the " nn name " label
+    let columns = (area.width as usize).saturating_sub(label_width + 7).max(1);
+    let samples = dp.history.resample(now, window.duration(), columns);
+
+    let lines: Vec<Line> = bits
+        .iter()
+        .map(|(bit, name)| {
+            let mut spans = vec![Span::styled(
+                format!(" {:2} {:<width$.width$} ", bit, name, width = label_width),
+                Style::default().fg(Color::White),
+            )];
+            // The history only holds the first bits of long coil arrays;
+            // the rows of later bits stay blank
+            let recorded = (*bit as u16) < MAX_TIMELINE_BITS;
+            spans.extend(samples.iter().map(|sample| match sample {
+                Some(value) if recorded => {
+                    if (*value as u64).checked_shr(*bit as u32).unwrap_or(0) & 1 == 1 {
+                        Span::styled("█", Style::default().fg(Color::Green))
+                    } else {
+                        Span::styled("▁", Style::default().fg(Color::DarkGray))
+                    }
+                }
+                _ => Span::raw(" "),
+            }));
+            Line::from(spans)
+        })
+        .collect();
+
+    f.render_widget(Paragraph::new(lines).block(block), area);
+}
+
 fn draw_error_details(f: &mut Frame, area: Rect, app: &App) {
-    if let Some(idx) = app.table_state.selected()
-        && let Some(dp) = app.datapoints.get(idx)
+    if let Some(dp) = app.selected_datapoint()
         && let Some(ref error) = dp.error
     {
-        let error_text = vec![Line::from(vec![
+        let mut error_text = vec![Line::from(vec![
             Span::styled(
                 "Error Details: ",
                 Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
             ),
             Span::raw(error.as_str()),
         ])];
+        error_text.extend(write_status_line(dp));
         let error_widget = Paragraph::new(error_text).block(
             Block::default()
                 .borders(Borders::ALL)
@@ -261,43 +1006,451 @@ fn draw_error_details(f: &mut Frame, area: Rect, app: &App) {
 }
 
 fn draw_bitfield_details(f: &mut Frame, area: Rect, app: &App) {
-    if let Some(idx) = app.table_state.selected()
-        && let Some(dp) = app.datapoints.get(idx)
+    if let Some(dp) = app.selected_datapoint()
         && let Some(bitfield_status) = dp.get_bitfield_status()
     {
-        let mut lines = vec![];
-        for (bit, name, is_set) in bitfield_status {
-            let status_char = if is_set { "✓" } else { "✗" };
-            let color = if is_set { Color::Green } else { Color::Gray };
-            lines.push(Line::from(vec![
+        let mut lines: Vec<Line> = write_status_line(dp).into_iter().collect();
+        let first_bit_line = lines.len();
+        for (i, field) in bitfield_status.into_iter().enumerate() {
+            let read_only = field.is_flag() && dp.read_only_bits.contains(&field.bit);
+            let mut spans = if field.is_flag() {
+                let status_char = if field.is_set() { "✓" } else { "✗" };
+                let color = if field.is_set() {
+                    Color::Green
+                } else {
+                    Color::Gray
+                };
+                vec![
+                    Span::styled(
+                        format!("  Bit {:2}: ", field.bit),
+                        Style::default().fg(Color::Yellow),
+                    ),
+                    Span::styled(
+                        status_char,
+                        Style::default().fg(color).add_modifier(Modifier::BOLD),
+                    ),
+                    Span::raw(" "),
+                    Span::raw(field.name),
+                ]
+            } else {
+                let last = field.bit + field.width - 1;
+                let mut spans = vec![
+                    Span::styled(
+                        format!("  Bits {}-{}: ", field.bit, last),
+                        Style::default().fg(Color::Yellow),
+                    ),
+                    Span::styled(
+                        field.value.to_string(),
+                        Style::default().add_modifier(Modifier::BOLD),
+                    ),
+                    Span::raw(" "),
+                    Span::raw(field.name),
+                ];
+                // Enum colors, unknown codes stand out
+                match field.label {
+                    Some(Some(label)) => {
+                        let style = label
+                            .color()
+                            .and_then(|c| c.parse::<Color>().ok())
+                            .map(|c| Style::default().fg(c))
+                            .unwrap_or_default();
+                        spans.push(Span::raw(" = "));
+                        spans.push(Span::styled(label.label().to_string(), style));
+                    }
+                    Some(None) => {
+                        spans.push(Span::raw(" = "));
+                        spans.push(Span::styled(
+                            "Unknown",
+                            Style::default()
+                                .fg(Color::Magenta)
+                                .add_modifier(Modifier::BOLD),
+                        ));
+                    }
+                    None => {}
+                }
+                spans
+            };
+            if dp.writable && read_only {
+                spans.push(Span::styled(
+                    " (read-only)",
+                    Style::default().fg(Color::DarkGray),
+                ));
+            }
+            let mut line = Line::from(spans);
+            if app.bit_cursor == Some(i) {
+                line = line.style(
+                    Style::default()
+                        .bg(Color::DarkGray)
+                        .add_modifier(Modifier::BOLD),
+                );
+            }
+            lines.push(line);
+        }
+
+        // Keep the cursor visible when there are more bits than rows
+        let visible = area.height.saturating_sub(2) as usize;
+        let scroll = app
+            .bit_cursor
+            .map(|cursor| (first_bit_line + cursor + 1).saturating_sub(visible))
+            .unwrap_or(0);
+
+        let title = if app.bit_cursor.is_some() {
+            "Bitfield Details (↑/↓ select, Space toggle, b/Esc back)"
+        } else if dp.writable {
+            "Bitfield Details (b to edit bits)"
+        } else {
+            "Bitfield Details"
+        };
+        let bitfield_widget = Paragraph::new(lines).scroll((scroll as u16, 0)).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(title)
+                .style(Style::default().fg(Color::Cyan)),
+        );
+        f.render_widget(bitfield_widget, area);
+    }
+}
+
+/// Event log, newest first. Unacknowledged alarms are marked with `!`.
+fn draw_event_log(f: &mut Frame, area: Rect, app: &App) {
+    let lines: Vec<Line> = app
+        .events
+        .events()
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, event)| {
+            let color = match event.kind {
+                EventKind::AlarmRaised => event.severity.map(severity_color).unwrap_or(Color::Red),
+                EventKind::AlarmCleared | EventKind::ConnectionRestored => Color::Green,
+                EventKind::AlarmAcknowledged | EventKind::BitChanged => Color::Gray,
+                EventKind::ConnectionLost | EventKind::ModbusException => Color::Red,
+            };
+            let marker = if event.needs_acknowledgement() {
+                "!"
+            } else {
+                " "
+            };
+            let source = match event.datapoint {
+                Some(ref datapoint) if app.servers.len() > 1 => {
+                    format!("{}/{}", event.server, datapoint)
+                }
+                Some(ref datapoint) => datapoint.clone(),
+                None => event.server.clone(),
+            };
+            let mut line = Line::from(vec![
                 Span::styled(
-                    format!("  Bit {:2}: ", bit),
-                    Style::default().fg(Color::Yellow),
+                    marker,
+                    Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
                 ),
+                Span::raw(format!("{} ", event.timestamp.format("%m-%d %H:%M:%S"))),
                 Span::styled(
-                    status_char,
+                    format!("{:<10}", event.kind.label()),
                     Style::default().fg(color).add_modifier(Modifier::BOLD),
                 ),
-                Span::raw(" "),
-                Span::raw(name),
-            ]));
-        }
+                Span::styled(format!("{:<24} ", source), Style::default().fg(Color::Cyan)),
+                Span::raw(event.message.clone()),
+            ]);
+            if app.log_cursor == Some(i) {
+                line = line.style(
+                    Style::default()
+                        .bg(Color::DarkGray)
+                        .add_modifier(Modifier::BOLD),
+                );
+            }
+            line
+        })
+        .collect();
+
+    // Keep the cursor visible
+    let visible = area.height.saturating_sub(2) as usize;
+    let scroll = app
+        .log_cursor
+        .map(|cursor| (cursor + 1).saturating_sub(visible))
+        .unwrap_or(0);
+
+    let unacknowledged = app.events.unacknowledged();
+    let title = if unacknowledged > 0 {
+        format!(
+            "Event Log - {} unacknowledged (↑/↓ select, a acknowledge, l/Esc close)",
+            unacknowledged
+        )
+    } else {
+        "Event Log (↑/↓ select, l/Esc close)".to_string()
+    };
+    let log = Paragraph::new(lines)
+        .scroll((scroll as u16, 0))
+        .block(Block::default().borders(Borders::ALL).title(title));
+    f.render_widget(log, area);
+}
+
+fn draw_bit_confirm(f: &mut Frame, app: &App) {
+    let Some(ref toggle) = app.pending_bit else {
+        return;
+    };
+
+    let lines = vec![
+        Line::from(format!(
+            "Set bit {} ({}) to {}?",
+            toggle.bit, toggle.name, toggle.value as u8
+        )),
+        Line::from(vec![
+            Span::styled("y/Enter", Style::default().fg(Color::Yellow)),
+            Span::raw(" confirm | "),
+            Span::styled("n/Esc", Style::default().fg(Color::Yellow)),
+            Span::raw(" cancel"),
+        ]),
+    ];
+
+    let area = centered_rect(f.area(), 50, 4);
+    let dialog = Paragraph::new(lines).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title("Confirm Bit Write")
+            .style(Style::default().fg(Color::Cyan)),
+    );
+    f.render_widget(Clear, area);
+    f.render_widget(dialog, area);
+}
+
+fn write_status_line(dp: &Datapoint) -> Option<Line<'static>> {
+    let status = dp.write_status.as_ref()?;
+    let time = status.timestamp.format("%H:%M:%S");
+    let line = match status.error {
+        None => Line::from(vec![
+            Span::styled(
+                "Write: ",
+                Style::default()
+                    .fg(Color::Green)
+                    .add_modifier(Modifier::BOLD),
+            ),
+            Span::raw(format!("{} written at {}", status.value, time)),
+        ]),
+        Some(ref error) => Line::from(vec![
+            Span::styled(
+                "Write failed: ",
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            ),
+            Span::raw(format!("{} ({} at {})", error, status.value, time)),
+        ]),
+    };
+    Some(line)
+}
+
+/// Full text of a string datapoint, wrapped to the panel width.
+fn draw_text_details(f: &mut Frame, area: Rect, app: &App) {
+    let Some(dp) = app.selected_datapoint() else {
+        return;
+    };
+    let Some(DataValue::String(ref text)) = dp.value else {
+        return;
+    };
+
+    let mut lines = vec![Line::from(text.as_str())];
+    if let Some(line) = write_status_line(dp) {
+        lines.push(line);
+    }
+    let widget = Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(format!("{} ({} chars)", dp.name, text.chars().count()))
+            .style(Style::default().fg(Color::Cyan)),
+    );
+    f.render_widget(widget, area);
+}
 
-        let bitfield_widget = Paragraph::new(lines).block(
+fn draw_write_details(f: &mut Frame, area: Rect, app: &App) {
+    if let Some(dp) = app.selected_datapoint()
+        && let Some(line) = write_status_line(dp)
+    {
+        let widget = Paragraph::new(line).block(
             Block::default()
                 .borders(Borders::ALL)
-                .title("Bitfield Details")
+                .title("Details")
                 .style(Style::default().fg(Color::Cyan)),
         );
-        f.render_widget(bitfield_widget, area);
+        f.render_widget(widget, area);
     }
 }
 
-fn draw_footer(f: &mut Frame, area: Rect) {
+fn draw_edit_dialog(f: &mut Frame, app: &App) {
+    let Some(ref edit) = app.edit else {
+        return;
+    };
+    let Some(dp) = app.datapoints.get(edit.index) else {
+        return;
+    };
+
+    let mut lines = vec![Line::from(vec![
+        Span::styled("Address: ", Style::default().fg(Color::Yellow)),
+        Span::raw(if dp.register_type.is_bit() {
+            format!("{} ({:?})", dp.address, dp.register_type).to_lowercase()
+        } else {
+            format!("{} ({:?})", dp.address, dp.data_type).to_lowercase()
+        }),
+    ])];
+    if let Some(ref unit) = dp.unit {
+        lines.push(Line::from(vec![
+            Span::styled("Unit: ", Style::default().fg(Color::Yellow)),
+            Span::raw(unit.as_str()),
+        ]));
+    }
+    lines.push(Line::from(vec![
+        Span::styled("Value: ", Style::default().fg(Color::Yellow)),
+        Span::raw(edit.input.as_str()),
+        Span::styled("_", Style::default().add_modifier(Modifier::SLOW_BLINK)),
+    ]));
+
+    // Enum picker, scrolled to keep the selection visible
+    let first = edit
+        .option
+        .map(|i| (i + 1).saturating_sub(MAX_PICKER_LINES))
+        .unwrap_or(0);
+    for (i, option) in edit
+        .options
+        .iter()
+        .enumerate()
+        .skip(first)
+        .take(MAX_PICKER_LINES)
+    {
+        lines.push(if edit.option == Some(i) {
+            Line::from(Span::styled(
+                format!("> {}", option),
+                Style::default()
+                    .fg(Color::Black)
+                    .bg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD),
+            ))
+        } else {
+            Line::from(format!("  {}", option))
+        });
+    }
+    if let Some(ref error) = edit.error {
+        lines.push(Line::from(Span::styled(
+            error.as_str(),
+            Style::default().fg(Color::Red),
+        )));
+    }
+
+    let area = centered_rect(f.area(), 50, lines.len() as u16 + 2);
+    let dialog = Paragraph::new(lines).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(if edit.options.is_empty() {
+                format!("Write {} (Enter: send, Esc: cancel)", dp.name)
+            } else {
+                format!("Write {} (↑/↓: pick, Enter: send, Esc: cancel)", dp.name)
+            })
+            .style(Style::default().fg(Color::Cyan)),
+    );
+    f.render_widget(Clear, area);
+    f.render_widget(dialog, area);
+}
+
+fn draw_seek_prompt(f: &mut Frame, app: &App) {
+    let (Some(seek), Some(replay)) = (&app.seek, &app.replay) else {
+        return;
+    };
+
+    let mut lines = vec![
+        Line::from(vec![
+            Span::styled("Recording: ", Style::default().fg(Color::Yellow)),
+            Span::raw(format!(
+                "{} to {}",
+                replay.start.format("%Y-%m-%d %H:%M:%S"),
+                replay.end.format("%Y-%m-%d %H:%M:%S")
+            )),
+        ]),
+        Line::from(vec![
+            Span::styled("Time: ", Style::default().fg(Color::Yellow)),
+            Span::raw(seek.input.as_str()),
+            Span::styled("_", Style::default().add_modifier(Modifier::SLOW_BLINK)),
+        ]),
+    ];
+    if let Some(ref error) = seek.error {
+        lines.push(Line::from(Span::styled(
+            error.as_str(),
+            Style::default().fg(Color::Red),
+        )));
+    }
+
+    let area = centered_rect(f.area(), 50, lines.len() as u16 + 2);
+    let dialog = Paragraph::new(lines).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title("Seek to HH:MM:SS or YYYY-MM-DD HH:MM:SS (Enter: go, Esc: cancel)")
+            .style(Style::default().fg(Color::Cyan)),
+    );
+    f.render_widget(Clear, area);
+    f.render_widget(dialog, area);
+}
+
+fn centered_rect(area: Rect, percent_x: u16, height: u16) -> Rect {
+    let width = area.width * percent_x / 100;
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + area.height.saturating_sub(height) / 2,
+        width,
+        height: height.min(area.height),
+    }
+}
+
+fn draw_footer(f: &mut Frame, area: Rect, app: &App) {
+    if app.replay.is_some() {
+        draw_replay_footer(f, area);
+        return;
+    }
     let footer_text = vec![
         Span::raw("Controls: "),
         Span::styled("↑/↓", Style::default().fg(Color::Yellow)),
         Span::raw(" Navigate | "),
+        Span::styled("w", Style::default().fg(Color::Yellow)),
+        Span::raw(" Write | "),
+        Span::styled("b", Style::default().fg(Color::Yellow)),
+        Span::raw(" Edit bits | "),
+        Span::styled("g", Style::default().fg(Color::Yellow)),
+        Span::raw(" Chart | "),
+        Span::styled("t", Style::default().fg(Color::Yellow)),
+        Span::raw(" Window | "),
+        Span::styled("r", Style::default().fg(Color::Yellow)),
+        Span::raw(" Raw | "),
+        Span::styled("Tab", Style::default().fg(Color::Yellow)),
+        Span::raw(" Server filter | "),
+        Span::styled("l", Style::default().fg(Color::Yellow)),
+        Span::raw(" Log | "),
+        Span::styled("R", Style::default().fg(Color::Yellow)),
+        Span::raw(" Record | "),
+        Span::styled("q/Esc/Ctrl+C", Style::default().fg(Color::Yellow)),
+        Span::raw(" Quit"),
+    ];
+    let footer =
+        Paragraph::new(Line::from(footer_text)).block(Block::default().borders(Borders::ALL));
+    f.render_widget(footer, area);
+}
+
+fn draw_replay_footer(f: &mut Frame, area: Rect) {
+    let footer_text = vec![
+        Span::raw("Replay: "),
+        Span::styled("Space", Style::default().fg(Color::Yellow)),
+        Span::raw(" Play/Pause | "),
+        Span::styled("s", Style::default().fg(Color::Yellow)),
+        Span::raw(" Speed | "),
+        Span::styled("←/→", Style::default().fg(Color::Yellow)),
+        Span::raw(" Skip | "),
+        Span::styled("/", Style::default().fg(Color::Yellow)),
+        Span::raw(" Seek | "),
+        Span::styled("↑/↓", Style::default().fg(Color::Yellow)),
+        Span::raw(" Navigate | "),
+        Span::styled("g", Style::default().fg(Color::Yellow)),
+        Span::raw(" Chart | "),
+        Span::styled("t", Style::default().fg(Color::Yellow)),
+        Span::raw(" Window | "),
+        Span::styled("r", Style::default().fg(Color::Yellow)),
+        Span::raw(" Raw | "),
+        Span::styled("Tab", Style::default().fg(Color::Yellow)),
+        Span::raw(" Server filter | "),
+        Span::styled("l", Style::default().fg(Color::Yellow)),
+        Span::raw(" Log | "),
         Span::styled("q/Esc/Ctrl+C", Style::default().fg(Color::Yellow)),
         Span::raw(" Quit"),
     ];
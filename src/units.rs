@@ -0,0 +1,455 @@
+//! `units` subcommand: finds the unit IDs that respond behind a Modbus TCP
+//! gateway.
+//!
+//! Every unit is asked for one register. Units are probed in parallel over
+//! a few connections, since a unit that is not on the bus takes the whole
+//! timeout to answer, if the gateway answers at all.
+
+use crate::discover::{Outcome, Probe, Table};
+use crate::protocol::{ENCAPSULATED_INTERFACE, READ_DEVICE_ID};
+use anyhow::{Context as _, Result};
+use clap::Args;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex};
+use tokio::task::JoinSet;
+use tokio::time::Duration;
+use tokio_modbus::prelude::*;
+
+/// Read device ID codes for regular and basic stream access
+const REGULAR_IDENTIFICATION: u8 = 0x02;
+const BASIC_IDENTIFICATION: u8 = 0x01;
+
+#[derive(Args, Debug)]
+pub struct UnitsArgs {
+    /// Modbus TCP gateway to probe
+    #[arg(long)]
+    host: String,
+
+    #[arg(long, default_value_t = 502)]
+    port: u16,
+
+    /// First unit ID of the sweep
+    #[arg(long, default_value_t = 1)]
+    first: u8,
+
+    /// Last unit ID of the sweep
+    #[arg(long, default_value_t = 247)]
+    last: u8,
+
+    /// Register read from every unit
+    #[arg(long, default_value_t = 0, value_name = "ADDRESS")]
+    register: u16,
+
+    #[arg(long, value_enum, default_value = "holding")]
+    register_type: Table,
+
+    /// Time to wait for each response
+    #[arg(long, default_value_t = 300, value_name = "MS")]
+    timeout_ms: u64,
+
+    /// Units probed at the same time, each over its own connection
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u8).range(1..=32))]
+    concurrency: u8,
+
+    /// Read the device identification (FC43/14) of responding units
+    #[arg(long)]
+    identify: bool,
+}
+
+/// Probes the units, prints those that respond and a summary of the rest.
+pub async fn run(args: &UnitsArgs) -> Result<()> {
+    if args.first > args.last {
+        anyhow::bail!("--first must not be above --last");
+    }
+    let addr = tokio::net::lookup_host((args.host.as_str(), args.port))
+        .await
+        .with_context(|| format!("Failed to resolve {}", args.host))?
+        .next()
+        .with_context(|| format!("No address for {}", args.host))?;
+    eprintln!(
+        "Probing units {}-{} of {} with {} register {}",
+        args.first,
+        args.last,
+        addr,
+        args.register_type.name(),
+        args.register
+    );
+
+    let pending = Arc::new(Mutex::new(args.first..=args.last));
+    let mut workers = JoinSet::new();
+    for _ in 0..args.concurrency {
+        let mut probe = Probe::new(
+            addr,
+            args.first,
+            args.register_type,
+            Duration::from_millis(args.timeout_ms),
+        );
+        let pending = pending.clone();
+        let (register, identify) = (args.register, args.identify);
+        workers.spawn(async move {
+            let mut results = Vec::new();
+            while let Some(unit) = next_unit(&pending) {
+                probe.set_unit(unit);
+                results.push(probe_unit(&mut probe, unit, register, identify).await);
+            }
+            (results, probe.requests)
+        });
+    }
+
+    let mut results = Vec::new();
+    let mut requests = 0;
+    while let Some(worker) = workers.join_next().await {
+        let (found, sent) = worker.context("Probe task failed")?;
+        results.extend(found);
+        requests += sent;
+    }
+    results.sort_by_key(|result| result.unit);
+
+    print_units(&results);
+    eprintln!("{}", summary(&results, requests));
+    Ok(())
+}
+
+fn next_unit(pending: &Mutex<RangeInclusive<u8>>) -> Option<u8> {
+    pending.lock().ok()?.next()
+}
+
+/// Answer of one unit.
+struct UnitResult {
+    unit: u8,
+    /// Answer to the register read, or why it could not be made sense of
+    outcome: Result<Outcome, String>,
+    /// Identification objects by ID, or why they could not be read
+    identification: Option<Result<BTreeMap<u8, String>, String>>,
+}
+
+impl UnitResult {
+    /// Whether a device answered, as opposed to nothing or only the gateway.
+    fn responds(&self) -> bool {
+        match self.outcome {
+            Ok(Outcome::Values(_)) => true,
+            Ok(Outcome::Rejected(exception)) => !is_gateway_exception(exception),
+            Ok(Outcome::NoResponse) | Err(_) => false,
+        }
+    }
+}
+
+fn is_gateway_exception(exception: Exception) -> bool {
+    matches!(
+        exception,
+        Exception::GatewayPathUnavailable | Exception::GatewayTargetDevice
+    )
+}
+
+/// Reads the register of one unit. Errors are kept in the result, so one
+/// misbehaving device does not end the sweep.
+async fn probe_unit(probe: &mut Probe, unit: u8, register: u16, identify: bool) -> UnitResult {
+    let mut result = UnitResult {
+        unit,
+        outcome: probe
+            .read(register, 1)
+            .await
+            .map_err(|e| format!("{:#}", e)),
+        identification: None,
+    };
+    if identify && result.responds() {
+        let objects = identification(probe).await;
+        result.identification = Some(objects.unwrap_or_else(|e| Err(format!("{:#}", e))));
+    }
+    result
+}
+
+/// Reads the regular identification objects, falling back to the basic
+/// ones for devices that only have those.
+async fn identification(probe: &mut Probe) -> Result<Result<BTreeMap<u8, String>, String>> {
+    let rejected = |outcome: &Outcome| {
+        matches!(
+            outcome,
+            Outcome::Rejected(Exception::IllegalDataValue | Exception::IllegalDataAddress)
+        )
+    };
+    let result = match read_identification(probe, REGULAR_IDENTIFICATION).await? {
+        Err(IdentificationError::Failed(ref outcome)) if rejected(outcome) => {
+            read_identification(probe, BASIC_IDENTIFICATION).await?
+        }
+        result => result,
+    };
+    Ok(result.map_err(|e| e.to_string()))
+}
+
+/// Why a unit's identification could not be read.
+enum IdentificationError {
+    /// Exception or no response
+    Failed(Outcome),
+    Invalid(String),
+}
+
+impl std::fmt::Display for IdentificationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IdentificationError::Failed(Outcome::Rejected(exception)) => write!(f, "{}", exception),
+            IdentificationError::Failed(_) => write!(f, "No response"),
+            IdentificationError::Invalid(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+/// Streams the objects of one identification level, following the "more
+/// follows" flag.
+async fn read_identification(
+    probe: &mut Probe,
+    code: u8,
+) -> Result<Result<BTreeMap<u8, String>, IdentificationError>> {
+    let mut objects = BTreeMap::new();
+    let mut next = 0;
+    loop {
+        let request = Request::Custom(
+            ENCAPSULATED_INTERFACE,
+            vec![READ_DEVICE_ID, code, next].into(),
+        );
+        let data = match probe.call(request).await? {
+            Some(Ok(Response::Custom(ENCAPSULATED_INTERFACE, data))) => data,
+            Some(Ok(response)) => {
+                let reason = format!("Unexpected response {:?}", response);
+                return Ok(Err(IdentificationError::Invalid(reason)));
+            }
+            Some(Err(exception)) => {
+                return Ok(Err(IdentificationError::Failed(Outcome::Rejected(
+                    exception,
+                ))));
+            }
+            None => return Ok(Err(IdentificationError::Failed(Outcome::NoResponse))),
+        };
+        let (more, following) = match parse_identification(&data, &mut objects) {
+            Ok(parsed) => parsed,
+            Err(reason) => return Ok(Err(IdentificationError::Invalid(reason))),
+        };
+        // A device that keeps repeating itself would loop forever
+        if !more || following <= next {
+            return Ok(Ok(objects));
+        }
+        next = following;
+    }
+}
+
+/// Adds the objects of one response; returns the "more follows" flag and
+/// the next object ID.
+fn parse_identification(
+    data: &[u8],
+    objects: &mut BTreeMap<u8, String>,
+) -> Result<(bool, u8), String> {
+    let [
+        READ_DEVICE_ID,
+        _code,
+        _conformity,
+        more,
+        next,
+        count,
+        ref rest @ ..,
+    ] = *data
+    else {
+        return Err("Malformed device identification response".to_string());
+    };
+    let truncated = || "Truncated device identification object".to_string();
+    let mut rest = rest;
+    for _ in 0..count {
+        let [id, len, ref tail @ ..] = *rest else {
+            return Err(truncated());
+        };
+        let value = tail.get(..len as usize).ok_or_else(truncated)?;
+        objects.insert(id, String::from_utf8_lossy(value).trim().to_string());
+        rest = &tail[len as usize..];
+    }
+    Ok((more == 0xFF, next))
+}
+
+fn object_name(id: u8) -> String {
+    match id {
+        0x00 => "VendorName".to_string(),
+        0x01 => "ProductCode".to_string(),
+        0x02 => "MajorMinorRevision".to_string(),
+        0x03 => "VendorUrl".to_string(),
+        0x04 => "ProductName".to_string(),
+        0x05 => "ModelName".to_string(),
+        0x06 => "UserApplicationName".to_string(),
+        id => format!("Object 0x{:02X}", id),
+    }
+}
+
+/// Prints the responding units with their register value or exception,
+/// the units that failed with their error, and the identification objects
+/// below each.
+fn print_units(results: &[UnitResult]) {
+    let listed: Vec<&UnitResult> = results
+        .iter()
+        .filter(|r| r.responds() || r.outcome.is_err())
+        .collect();
+    if listed.is_empty() {
+        return;
+    }
+    println!("  UNIT  RESPONSE");
+    for result in listed {
+        let response = match result.outcome {
+            Ok(Outcome::Values(ref words)) => format!("0x{:04X} ({})", words[0], words[0]),
+            Ok(Outcome::Rejected(exception)) => exception.to_string(),
+            Ok(Outcome::NoResponse) => continue,
+            Err(ref error) => format!("Error: {}", error),
+        };
+        println!("  {:>4}  {}", result.unit, response);
+        match result.identification {
+            Some(Ok(ref objects)) if objects.is_empty() => {
+                println!("          No identification objects");
+            }
+            Some(Ok(ref objects)) => {
+                for (&id, value) in objects {
+                    println!("          {}: {}", object_name(id), value);
+                }
+            }
+            Some(Err(ref error)) => println!("          Identification: {}", error),
+            None => {}
+        }
+    }
+}
+
+fn summary(results: &[UnitResult], requests: u32) -> String {
+    let responding = results.iter().filter(|r| r.responds()).count();
+    let mut summary = format!(
+        "{} of {} units respond, {} requests",
+        responding,
+        results.len(),
+        requests
+    );
+    let mut absent: BTreeMap<String, u32> = BTreeMap::new();
+    let (mut unanswered, mut failed) = (0, 0);
+    for result in results {
+        match result.outcome {
+            Ok(Outcome::Rejected(exception)) if is_gateway_exception(exception) => {
+                *absent.entry(exception.to_string()).or_default() += 1;
+            }
+            Ok(Outcome::NoResponse) => unanswered += 1,
+            Err(_) => failed += 1,
+            _ => {}
+        }
+    }
+    for (exception, count) in &absent {
+        let _ = write!(
+            summary,
+            "; {} answered by the gateway ({})",
+            count, exception
+        );
+    }
+    if unanswered > 0 {
+        let _ = write!(summary, "; {} without response", unanswered);
+    }
+    if failed > 0 {
+        let _ = write!(summary, "; {} failed", failed);
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::simulate::Simulator;
+    use serde_json::json;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[tokio::test]
+    async fn finds_the_served_units() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = Config::from_json(json!({
+            "server": { "protocol": "modbus", "host": "127.0.0.1", "name": "bus" },
+            "datapoints": [
+                { "name": "first", "address": 0, "length": 1, "unit_id": 3,
+                  "simulate": { "generator": "constant", "value": 42 } },
+                { "name": "second", "address": 10, "length": 1, "unit_id": 7 },
+            ],
+        }))
+        .unwrap();
+        let simulator = Simulator::new(&config.servers[0], config.datapoints).unwrap();
+        tokio::spawn(simulator.serve(listener));
+
+        let mut probe = Probe::new(addr, 1, Table::Holding, Duration::from_secs(1));
+        let mut results = Vec::new();
+        for unit in 1..=8 {
+            probe.set_unit(unit);
+            results.push(probe_unit(&mut probe, unit, 0, true).await);
+        }
+        let responding: Vec<u8> = results
+            .iter()
+            .filter(|r| r.responds())
+            .map(|r| r.unit)
+            .collect();
+        assert_eq!(responding, [3, 7]);
+        assert!(matches!(results[2].outcome, Ok(Outcome::Values(ref words)) if words == &[42]));
+        assert!(matches!(
+            results[6].outcome,
+            Ok(Outcome::Rejected(Exception::IllegalDataAddress))
+        ));
+        let Some(Ok(ref objects)) = results[2].identification else {
+            panic!("no identification");
+        };
+        assert_eq!(objects[&1], "bus");
+        assert!(results[0].identification.is_none());
+        assert!(summary(&results, probe.requests).starts_with("2 of 8 units respond"));
+    }
+
+    /// Serves register 0 as the unit ID, except that unit 2 answers with
+    /// no registers, and drops the connection on identification requests.
+    async fn misbehaving(mut stream: TcpStream) {
+        loop {
+            let mut header = [0u8; 7];
+            if stream.read_exact(&mut header).await.is_err() {
+                return;
+            }
+            let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+            let mut pdu = vec![0; length - 1];
+            stream.read_exact(&mut pdu).await.unwrap();
+            let unit = header[6];
+            let response = match pdu[0] {
+                ENCAPSULATED_INTERFACE => return,
+                _ if unit == 2 => vec![0x03, 0],
+                _ => vec![0x03, 2, 0, unit],
+            };
+            let mut frame = header[..4].to_vec();
+            frame.extend((response.len() as u16 + 1).to_be_bytes());
+            frame.push(unit);
+            frame.extend(response);
+            stream.write_all(&frame).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn continues_after_a_failing_unit() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(misbehaving(stream));
+            }
+        });
+
+        let mut probe = Probe::new(addr, 1, Table::Holding, Duration::from_secs(1));
+        let mut results = Vec::new();
+        for unit in 1..=3 {
+            probe.set_unit(unit);
+            results.push(probe_unit(&mut probe, unit, 0, true).await);
+        }
+        assert!(matches!(results[0].outcome, Ok(Outcome::Values(ref words)) if words == &[1]));
+        let Err(ref error) = results[1].outcome else {
+            panic!("unit 2 did not fail");
+        };
+        assert!(error.starts_with("Unexpected response"), "{}", error);
+        assert!(matches!(results[2].outcome, Ok(Outcome::Values(ref words)) if words == &[3]));
+        // Identification failed, but the units are still listed
+        assert!(matches!(results[0].identification, Some(Err(_))));
+        assert!(matches!(results[2].identification, Some(Err(_))));
+        assert!(summary(&results, probe.requests).starts_with("2 of 3 units respond"));
+        assert!(summary(&results, probe.requests).ends_with("; 1 failed"));
+    }
+}